@@ -48,7 +48,19 @@ impl AABB {
         }
     }
 
+    pub fn surface_area(&self) -> f64 {
+        let d = self.maxmum - self.minmum;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (self.minmum + self.maxmum) * 0.5
+    }
+
     pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        // The slabs narrow one interval; testing each against the ray's own interval alone
+        // would accept rays that pass beside the box.
+        let (mut t_min, mut t_max) = (t_min, t_max);
         for a in 0..3 {
             let inv_d = 1.0 / r.dir.get(a);
             let mut t0 = (self.minmum.get(a) - r.orig.get(a)) * inv_d;
@@ -60,11 +72,10 @@ impl AABB {
                 std::mem::swap(&mut t0, &mut t1)
             }
             //println!("{},{}\n",t0,t1);
-            let ans1 = fmax(t0, t_min);
-            let ans2 = fmin(t1, t_max);
+            t_min = fmax(t0, t_min);
+            t_max = fmin(t1, t_max);
             //println!("{},{}\t",t1,t_max);
-            if ans2 <= ans1 {
-                //if ans2 != ans1 {println!("{},{}\n",ans2,ans1);}
+            if t_max <= t_min {
                 return false;
             }
             // if a == 0 {
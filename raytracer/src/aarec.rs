@@ -7,7 +7,7 @@ use crate::{
     camera::degrees_to_radians,
     hittable::{self, HitRecord, Hittable},
     materia::Material,
-    rtweekend::random_double2,
+    sampler::sample_2d,
     Ray, Vec3,
 };
 pub struct XyRect {
@@ -45,9 +45,10 @@ impl Hittable for XyRect {
     }
 
     fn random(&self, o: Vec3) -> Vec3 {
+        let (s, t) = sample_2d();
         let random_point = Vec3::new(
-            random_double2(self.x0, self.x1),
-            random_double2(self.y0, self.y1),
+            self.x0 + s * (self.x1 - self.x0),
+            self.y0 + t * (self.y1 - self.y0),
             self.k,
         );
         random_point - o
@@ -79,6 +80,22 @@ impl Hittable for XyRect {
             Vec3::new(self.x1, self.y1, self.k + 0.0001),
         ))
     }
+
+    fn area(&self) -> f64 {
+        (self.x1 - self.x0) * (self.y1 - self.y0)
+    }
+
+    fn power(&self) -> f64 {
+        self.mp.emitted_power(self.area())
+    }
+
+    fn emitters(self: Arc<Self>) -> Vec<Arc<dyn Hittable>> {
+        if self.mp.is_emissive() {
+            vec![self]
+        } else {
+            Vec::new()
+        }
+    }
 }
 
 pub struct XzRect {
@@ -116,10 +133,11 @@ impl Hittable for XzRect {
     }
 
     fn random(&self, o: Vec3) -> Vec3 {
+        let (s, t) = sample_2d();
         let random_point = Vec3::new(
-            random_double2(self.x0, self.x1),
+            self.x0 + s * (self.x1 - self.x0),
             self.k,
-            random_double2(self.z0, self.z1),
+            self.z0 + t * (self.z1 - self.z0),
         );
         random_point - o
     }
@@ -151,6 +169,22 @@ impl Hittable for XzRect {
             Vec3::new(self.x1, self.k + 0.0001, self.z1),
         ))
     }
+
+    fn area(&self) -> f64 {
+        (self.x1 - self.x0) * (self.z1 - self.z0)
+    }
+
+    fn power(&self) -> f64 {
+        self.mp.emitted_power(self.area())
+    }
+
+    fn emitters(self: Arc<Self>) -> Vec<Arc<dyn Hittable>> {
+        if self.mp.is_emissive() {
+            vec![self]
+        } else {
+            Vec::new()
+        }
+    }
 }
 
 pub struct YzRect {
@@ -188,10 +222,11 @@ impl Hittable for YzRect {
     }
 
     fn random(&self, o: Vec3) -> Vec3 {
+        let (s, t) = sample_2d();
         let random_point = Vec3::new(
             self.k,
-            random_double2(self.y0, self.y1),
-            random_double2(self.z0, self.z1),
+            self.y0 + s * (self.y1 - self.y0),
+            self.z0 + t * (self.z1 - self.z0),
         );
         random_point - o
     }
@@ -223,6 +258,22 @@ impl Hittable for YzRect {
             Vec3::new(self.k + 0.0001, self.y1, self.z1),
         ))
     }
+
+    fn area(&self) -> f64 {
+        (self.y1 - self.y0) * (self.z1 - self.z0)
+    }
+
+    fn power(&self) -> f64 {
+        self.mp.emitted_power(self.area())
+    }
+
+    fn emitters(self: Arc<Self>) -> Vec<Arc<dyn Hittable>> {
+        if self.mp.is_emissive() {
+            vec![self]
+        } else {
+            Vec::new()
+        }
+    }
 }
 
 pub struct Translate {
@@ -267,6 +318,24 @@ impl Hittable for Translate {
     fn random(&self, _o: Vec3) -> Vec3 {
         self.ptr.random(_o - self.offset)
     }
+
+    fn area(&self) -> f64 {
+        self.ptr.area()
+    }
+
+    fn power(&self) -> f64 {
+        self.ptr.power()
+    }
+
+    fn emitters(self: Arc<Self>) -> Vec<Arc<dyn Hittable>> {
+        let offset = self.offset;
+        self.ptr
+            .clone()
+            .emitters()
+            .into_iter()
+            .map(|light| Arc::new(Translate::new(light, offset)) as Arc<dyn Hittable>)
+            .collect()
+    }
 }
 pub struct RotateY {
     pub ptr: Arc<dyn Hittable>,
@@ -327,6 +396,22 @@ impl RotateY {
             bbox,
         }
     }
+
+    fn to_object(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x - self.sin_theta * v.z,
+            v.y,
+            self.cos_theta * v.z + self.sin_theta * v.x,
+        )
+    }
+
+    fn to_world(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x + self.sin_theta * v.z,
+            v.y,
+            self.cos_theta * v.z - self.sin_theta * v.x,
+        )
+    }
 }
 
 impl Hittable for RotateY {
@@ -367,6 +452,33 @@ impl Hittable for RotateY {
         let output_box = self.bbox.clone();
         Some(output_box)
     }
+
+    // A rotation keeps solid angles, so the object's pdf holds for the rotated direction.
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
+        self.ptr.pdf_value(&self.to_object(*o), &self.to_object(*v))
+    }
+
+    fn random(&self, o: Vec3) -> Vec3 {
+        self.to_world(self.ptr.random(self.to_object(o)))
+    }
+
+    fn area(&self) -> f64 {
+        self.ptr.area()
+    }
+
+    fn power(&self) -> f64 {
+        self.ptr.power()
+    }
+
+    fn emitters(self: Arc<Self>) -> Vec<Arc<dyn Hittable>> {
+        let angle = self.sin_theta.atan2(self.cos_theta).to_degrees();
+        self.ptr
+            .clone()
+            .emitters()
+            .into_iter()
+            .map(|light| Arc::new(RotateY::new(light, angle)) as Arc<dyn Hittable>)
+            .collect()
+    }
 }
 
 // pub struct RotateZ {
@@ -656,6 +768,40 @@ impl Hittable for Triangle {
         ) + Vec3::new(0.0001, 0.0001, 0.0001);
         Some(AABB::new(a1, a2))
     }
+
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
+        let n = Vec3::cross(self.a2 - self.a1, self.a3 - self.a1);
+        let area = n.len() / 2.0;
+        if area <= 0.0 {
+            return 0.0;
+        }
+        if let Some(rec) = self.hit(&Ray::new(*o, *v, 0.0), 0.001, f64::INFINITY) {
+            let distance_squared = rec.t * rec.t * v.squared_length();
+            let cosine = Vec3::dot(*v, n.unit()).abs() / v.len();
+            return distance_squared / (cosine * area);
+        }
+        0.0
+    }
+
+    fn random(&self, o: Vec3) -> Vec3 {
+        sample_triangle(self.a1, self.a2, self.a3) - o
+    }
+
+    fn area(&self) -> f64 {
+        Vec3::cross(self.a2 - self.a1, self.a3 - self.a1).len() / 2.0
+    }
+
+    fn power(&self) -> f64 {
+        self.mp.emitted_power(self.area())
+    }
+
+    fn emitters(self: Arc<Self>) -> Vec<Arc<dyn Hittable>> {
+        if self.mp.is_emissive() {
+            vec![self]
+        } else {
+            Vec::new()
+        }
+    }
 }
 
 pub fn max3(a: f64, b: f64, c: f64) -> f64 {
@@ -665,3 +811,10 @@ pub fn max3(a: f64, b: f64, c: f64) -> f64 {
 pub fn min3(a: f64, b: f64, c: f64) -> f64 {
     fmin(fmin(a, b), c)
 }
+
+// A point spread uniformly over the triangle abc.
+pub fn sample_triangle(a: Vec3, b: Vec3, c: Vec3) -> Vec3 {
+    let (r1, r2) = sample_2d();
+    let s = r1.sqrt();
+    a * (1.0 - s) + b * (s * (1.0 - r2)) + c * (s * r2)
+}
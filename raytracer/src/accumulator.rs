@@ -0,0 +1,305 @@
+use std::{
+    convert::TryInto,
+    fs::{self, File},
+    io::{BufWriter, Write},
+};
+
+use crate::{
+    aov::Aov,
+    cache::Fnv,
+    framebuffer::Framebuffer,
+    render::{RenderOutput, RenderSettings},
+    Vec3,
+};
+
+const MAGIC: &[u8; 8] = b"RTCKPT3\n";
+
+// What the samples in a checkpoint were rendered with, besides the image size and passes
+// the accumulator itself records. Resuming with anything else would average two different
+// estimators, so `load_checkpoint` refuses it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CheckpointKey {
+    pub seed: u64,
+    pub sampler: String,
+    // See `SamplerKind::layout_samples`: a stratified render can be resumed, but not
+    // taken to another --spp, as that would move the strata of the samples already taken.
+    pub layout_samples: u64,
+    pub max_depth: i32,
+    // A hash of the scene file's text.
+    pub scene: u64,
+}
+
+impl CheckpointKey {
+    pub fn new(settings: &RenderSettings, scene: &[u8]) -> Self {
+        let mut hash = Fnv::new();
+        hash.write(scene);
+        Self {
+            seed: settings.seed,
+            sampler: settings.sampler.name().to_string(),
+            layout_samples: settings.sampler.layout_samples(settings.samples_per_pixel) as u64,
+            max_depth: settings.max_depth,
+            scene: hash.finish(),
+        }
+    }
+}
+
+// Running sums of every pass rendered so far. `samples` is the number of samples per pixel
+// they hold; the object ID pass is not summed and keeps the value of the first sample.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Accumulator {
+    pub samples: usize,
+    pub color: Framebuffer,
+    pub aovs: Vec<(Aov, Framebuffer)>,
+}
+
+impl Accumulator {
+    pub fn new(width: usize, height: usize, aovs: &[Aov]) -> Self {
+        Self {
+            samples: 0,
+            color: Framebuffer::new(width, height),
+            aovs: aovs
+                .iter()
+                .map(|aov| (*aov, Framebuffer::new(width, height)))
+                .collect(),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.color.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.color.height
+    }
+
+    // Whether this can take samples for an image of the given size and passes.
+    pub fn fits(&self, width: usize, height: usize, aovs: &[Aov]) -> bool {
+        self.width() == width
+            && self.height() == height
+            && self
+                .aovs
+                .iter()
+                .map(|(aov, _)| *aov)
+                .eq(aovs.iter().copied())
+    }
+
+    // The current estimate: the sums divided by the sample count.
+    pub fn output(&self) -> RenderOutput {
+        let n = self.samples.max(1) as f64;
+        let average = |fb: &Framebuffer, n: f64| {
+            let mut out = fb.clone();
+            for c in out.pixels.iter_mut() {
+                *c = *c / n;
+            }
+            out
+        };
+        RenderOutput {
+            color: average(&self.color, n),
+            aovs: self
+                .aovs
+                .iter()
+                .map(|(aov, fb)| (*aov, average(fb, if aov.averaged() { n } else { 1.0 })))
+                .collect(),
+        }
+    }
+
+    // Written to a temporary file first and renamed, so a render killed mid-write
+    // still leaves the previous checkpoint behind.
+    pub fn save_checkpoint(&self, path: &str, key: &CheckpointKey) -> Result<(), String> {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&key.seed.to_le_bytes());
+        out.push(key.sampler.len() as u8);
+        out.extend_from_slice(key.sampler.as_bytes());
+        out.extend_from_slice(&key.layout_samples.to_le_bytes());
+        out.extend_from_slice(&key.max_depth.to_le_bytes());
+        out.extend_from_slice(&key.scene.to_le_bytes());
+        out.extend_from_slice(&(self.width() as u32).to_le_bytes());
+        out.extend_from_slice(&(self.height() as u32).to_le_bytes());
+        out.extend_from_slice(&(self.samples as u64).to_le_bytes());
+        out.extend_from_slice(&(self.aovs.len() as u32).to_le_bytes());
+        for (aov, _) in self.aovs.iter() {
+            out.push(aov.name().len() as u8);
+            out.extend_from_slice(aov.name().as_bytes());
+        }
+        for fb in std::iter::once(&self.color).chain(self.aovs.iter().map(|(_, fb)| fb)) {
+            for c in fb.pixels.iter() {
+                for v in [c.x, c.y, c.z].iter() {
+                    out.extend_from_slice(&v.to_le_bytes());
+                }
+            }
+        }
+
+        let tmp = format!("{}.tmp", path);
+        let write = || -> std::io::Result<()> {
+            let mut w = BufWriter::new(File::create(&tmp)?);
+            w.write_all(&out)?;
+            w.flush()?;
+            fs::rename(&tmp, path)
+        };
+        write().map_err(|e| format!("cannot write checkpoint {}: {}", path, e))
+    }
+
+    pub fn load_checkpoint(path: &str, key: &CheckpointKey) -> Result<Self, String> {
+        let data = fs::read(path).map_err(|e| format!("cannot read checkpoint {}: {}", path, e))?;
+        let bad = || format!("{} is not a valid checkpoint", path);
+        let mut reader = Reader {
+            data: &data,
+            pos: 0,
+        };
+        if reader.take(MAGIC.len()).ok_or_else(bad)? != MAGIC {
+            return Err(bad());
+        }
+        let seed = reader.u64().ok_or_else(bad)?;
+        let len = reader.take(1).ok_or_else(bad)?[0] as usize;
+        let sampler = std::str::from_utf8(reader.take(len).ok_or_else(bad)?).map_err(|_| bad())?;
+        let layout_samples = reader.u64().ok_or_else(bad)?;
+        let max_depth = reader.u32().ok_or_else(bad)? as i32;
+        let scene = reader.u64().ok_or_else(bad)?;
+        let mismatch = if seed != key.seed {
+            Some(format!("seed {}", seed))
+        } else if sampler != key.sampler {
+            Some(format!("the {} sampler", sampler))
+        } else if layout_samples != key.layout_samples {
+            Some(format!("strata laid out for --spp {}", layout_samples))
+        } else if max_depth != key.max_depth {
+            Some(format!("depth {}", max_depth))
+        } else if scene != key.scene {
+            Some("another scene file".to_string())
+        } else {
+            None
+        };
+        if let Some(what) = mismatch {
+            return Err(format!("checkpoint {} was rendered with {}", path, what));
+        }
+        let width = reader.u32().ok_or_else(bad)? as usize;
+        let height = reader.u32().ok_or_else(bad)? as usize;
+        let samples = reader.u64().ok_or_else(bad)? as usize;
+        let n_aovs = reader.u32().ok_or_else(bad)?;
+        let mut aovs = Vec::new();
+        for _ in 0..n_aovs {
+            let len = reader.take(1).ok_or_else(bad)?[0] as usize;
+            let name = reader.take(len).ok_or_else(bad)?;
+            let aov = std::str::from_utf8(name)
+                .ok()
+                .and_then(Aov::from_name)
+                .ok_or_else(bad)?;
+            aovs.push(aov);
+        }
+
+        // Check the size before allocating, so a corrupt header can't ask for the moon.
+        let expected = width
+            .checked_mul(height)
+            .and_then(|n| n.checked_mul(aovs.len() + 1))
+            .and_then(|n| n.checked_mul(3 * 8));
+        if expected != Some(data.len() - reader.pos) {
+            return Err(bad());
+        }
+        let mut acc = Accumulator::new(width, height, &aovs);
+        acc.samples = samples;
+        let buffers = std::iter::once(&mut acc.color).chain(acc.aovs.iter_mut().map(|(_, fb)| fb));
+        for fb in buffers {
+            for c in fb.pixels.iter_mut() {
+                *c = Vec3::new(
+                    reader.f64().ok_or_else(bad)?,
+                    reader.f64().ok_or_else(bad)?,
+                    reader.f64().ok_or_else(bad)?,
+                );
+            }
+        }
+        if reader.pos != data.len() {
+            return Err(bad());
+        }
+        Ok(acc)
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos + n)?;
+        self.pos += n;
+        Some(bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn f64(&mut self) -> Option<f64> {
+        Some(f64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> CheckpointKey {
+        CheckpointKey {
+            seed: 7,
+            sampler: "sobol".to_string(),
+            layout_samples: 0,
+            max_depth: 50,
+            scene: 0x1234,
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let mut acc = Accumulator::new(3, 2, &[Aov::Depth, Aov::ObjectId]);
+        acc.samples = 12;
+        acc.color.set(1, 1, Vec3::new(1.5, -2.0, 1e9));
+        acc.aovs[0].1.set(2, 0, Vec3::new(24.0, 24.0, 24.0));
+        acc.aovs[1].1.set(0, 1, Vec3::new(3.0, 3.0, 3.0));
+        let path = std::env::temp_dir().join("raytracer_test_checkpoint.bin");
+        let path = path.to_str().unwrap();
+        acc.save_checkpoint(path, &key()).unwrap();
+        let loaded = Accumulator::load_checkpoint(path, &key()).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(loaded, acc);
+
+        let output = loaded.output();
+        assert_eq!(
+            output.color.get(1, 1),
+            Vec3::new(0.125, -2.0 / 12.0, 1e9 / 12.0)
+        );
+        assert_eq!(output.aovs[0].1.get(2, 0), Vec3::new(2.0, 2.0, 2.0));
+        assert_eq!(output.aovs[1].1.get(0, 1), Vec3::new(3.0, 3.0, 3.0));
+    }
+
+    #[test]
+    fn test_checkpoint_rejects_other_settings() {
+        let acc = Accumulator::new(4, 4, &[]);
+        let path = std::env::temp_dir().join("raytracer_test_checkpoint_key.bin");
+        let path = path.to_str().unwrap();
+        acc.save_checkpoint(path, &key()).unwrap();
+        let mut other = key();
+        other.sampler = "halton".to_string();
+        let err = Accumulator::load_checkpoint(path, &other).unwrap_err();
+        assert!(err.ends_with("the sobol sampler"), "{}", err);
+        let mut other = key();
+        other.layout_samples = 64;
+        let err = Accumulator::load_checkpoint(path, &other).unwrap_err();
+        assert!(err.ends_with("strata laid out for --spp 0"), "{}", err);
+        let mut other = key();
+        other.scene += 1;
+        assert!(Accumulator::load_checkpoint(path, &other).is_err());
+
+        // A header claiming a huge image is caught before anything is allocated.
+        let mut data = fs::read(path).unwrap();
+        let width_at = MAGIC.len() + 8 + 1 + "sobol".len() + 8 + 4 + 8;
+        data[width_at..width_at + 8].copy_from_slice(&[0xff; 8]);
+        fs::write(path, &data).unwrap();
+        let result = Accumulator::load_checkpoint(path, &key());
+        fs::remove_file(path).unwrap();
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use image::{ImageBuffer, Rgb, RgbImage};
+
+use crate::{
+    camera::clamp,
+    framebuffer::{is_hdr_format, Framebuffer},
+    hittable::Hittable,
+    materia::ScatterRecord,
+    tonemap::ToneMapping,
+    Ray, Vec3,
+};
+
+// Extra passes taken from the first hit of each camera ray. Misses leave every pass at zero.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aov {
+    Albedo,
+    Normal,
+    Depth,
+    Position,
+    ObjectId,
+    Uv,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 6] = [
+        Aov::Albedo,
+        Aov::Normal,
+        Aov::Depth,
+        Aov::Position,
+        Aov::ObjectId,
+        Aov::Uv,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Aov::ALL.iter().copied().find(|aov| aov.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::ObjectId => "object_id",
+            Aov::Uv => "uv",
+        }
+    }
+
+    // Ids can't be blended at object edges, so that pass keeps the first sample only.
+    pub fn averaged(&self) -> bool {
+        *self != Aov::ObjectId
+    }
+
+    pub fn sample(&self, r: &Ray, world: &dyn Hittable) -> Vec3 {
+        let rec = match world.hit(r, 0.0001, f64::INFINITY) {
+            Some(rec) => rec,
+            None => return Vec3::zero(),
+        };
+        match self {
+            Aov::Albedo => {
+                let mut attenuation = Vec3::zero();
+                let mut scattered = Ray::new(Vec3::zero(), Vec3::zero(), 0.0);
+                let mut srec = ScatterRecord::new();
+                if rec
+                    .mat_ptr
+                    .scatter(r, &rec, &mut attenuation, &mut scattered, &mut srec)
+                {
+                    srec.attenuation
+                } else {
+                    // Emitters don't scatter, use their clamped emission instead.
+                    let e = rec.mat_ptr.emitted(r, &rec, rec.u, rec.v, &rec.p);
+                    Vec3::new(
+                        clamp(e.x, 0.0, 1.0),
+                        clamp(e.y, 0.0, 1.0),
+                        clamp(e.z, 0.0, 1.0),
+                    )
+                }
+            }
+            Aov::Normal => rec.normal.unit(),
+            Aov::Depth => {
+                let d = rec.t * r.dir.len();
+                Vec3::new(d, d, d)
+            }
+            Aov::Position => rec.p,
+            Aov::ObjectId => {
+                let id = rec.object_id as f64;
+                Vec3::new(id, id, id)
+            }
+            Aov::Uv => Vec3::new(rec.u, rec.v, 0.0),
+        }
+    }
+
+    // HDR formats get the raw values, 8-bit formats a visualisation of the pass.
+    pub fn save(&self, fb: &Framebuffer, path: &str) -> Result<(), String> {
+        if is_hdr_format(path) {
+            return fb.save(path, &ToneMapping::default());
+        }
+        self.to_rgb_image(fb)
+            .save(path)
+            .map_err(|e| format!("cannot write {}: {}", path, e))
+    }
+
+    pub fn to_rgb_image(&self, fb: &Framebuffer) -> RgbImage {
+        let (lo, hi) = bounds(fb);
+        let mut img: RgbImage = ImageBuffer::new(fb.width as u32, fb.height as u32);
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            let c = fb.get(x as usize, y as usize);
+            let c = match self {
+                Aov::Albedo | Aov::Uv => c,
+                Aov::Normal => c * 0.5 + Vec3::ones() * 0.5,
+                Aov::Depth => c / hi.x.max(1e-8),
+                Aov::Position => {
+                    let size = hi - lo;
+                    Vec3::new(
+                        (c.x - lo.x) / size.x.max(1e-8),
+                        (c.y - lo.y) / size.y.max(1e-8),
+                        (c.z - lo.z) / size.z.max(1e-8),
+                    )
+                }
+                Aov::ObjectId => id_color(c.x.round() as u32),
+            };
+            *pixel = Rgb([to_u8(c.x), to_u8(c.y), to_u8(c.z)]);
+        }
+        img
+    }
+}
+
+fn bounds(fb: &Framebuffer) -> (Vec3, Vec3) {
+    let mut lo = Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+    let mut hi = -lo;
+    for c in fb.pixels.iter() {
+        lo = Vec3::new(lo.x.min(c.x), lo.y.min(c.y), lo.z.min(c.z));
+        hi = Vec3::new(hi.x.max(c.x), hi.y.max(c.y), hi.z.max(c.z));
+    }
+    (lo, hi)
+}
+
+// A stable, well spread colour per id, black for misses.
+fn id_color(id: u32) -> Vec3 {
+    if id == 0 {
+        return Vec3::zero();
+    }
+    let h = id.wrapping_mul(0x9e37_79b1) ^ (id >> 15);
+    let h = h.wrapping_mul(0x85eb_ca6b);
+    Vec3::new(
+        ((h >> 24) & 0xff) as f64 / 255.0,
+        ((h >> 16) & 0xff) as f64 / 255.0,
+        ((h >> 8) & 0xff) as f64 / 255.0,
+    )
+}
+
+fn to_u8(x: f64) -> u8 {
+    (256.0 * clamp(x, 0.0, 0.999)) as u8
+}
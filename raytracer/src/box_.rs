@@ -6,6 +6,7 @@ use crate::{
     hittable::{self, Hittable},
     hittable_list::HittableList,
     materia::Material,
+    sampler::sample_1d,
     Vec3,
 };
 
@@ -75,6 +76,13 @@ impl Box_ {
             sides: s,
         }
     }
+
+    // In the order of `sides`: two xy, two xz, then two yz rectangles.
+    fn side_areas(&self) -> [f64; 6] {
+        let d = self.box_max - self.box_min;
+        let (xy, xz, yz) = ((d.x * d.y).abs(), (d.x * d.z).abs(), (d.y * d.z).abs());
+        [xy, xy, xz, xz, yz, yz]
+    }
 }
 
 impl Hittable for Box_ {
@@ -85,4 +93,90 @@ impl Hittable for Box_ {
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<crate::aabb::AABB> {
         Some(AABB::new(self.box_min, self.box_max))
     }
+
+    // Sides are picked by area, so points cover the surface uniformly. A line through the
+    // box crosses two sides and either could have been sampled.
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
+        let areas = self.side_areas();
+        let total: f64 = areas.iter().sum();
+        if total <= 0.0 {
+            return 0.0;
+        }
+        self.sides
+            .objects
+            .iter()
+            .zip(areas.iter())
+            .filter(|(_, area)| **area > 0.0)
+            .map(|(side, area)| area / total * side.pdf_value(o, v))
+            .sum()
+    }
+
+    fn random(&self, o: Vec3) -> Vec3 {
+        let areas = self.side_areas();
+        let mut r = sample_1d() * areas.iter().sum::<f64>();
+        for (side, area) in self.sides.objects.iter().zip(areas.iter()) {
+            if r < *area {
+                return side.random(o);
+            }
+            r -= area;
+        }
+        self.sides.objects[5].random(o)
+    }
+
+    fn area(&self) -> f64 {
+        self.side_areas().iter().sum()
+    }
+
+    fn power(&self) -> f64 {
+        self.sides.objects.iter().map(|side| side.power()).sum()
+    }
+
+    fn emitters(self: Arc<Self>) -> Vec<Arc<dyn Hittable>> {
+        Arc::new(self.sides.clone()).emitters()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{aarec::RotateY, materia::Lambertian, Ray};
+
+    #[test]
+    fn test_pdf_integrates_to_one() {
+        let b = RotateY::new(
+            Arc::new(Box_::new(
+                Vec3::new(-1.0, -0.5, -2.0),
+                Vec3::new(1.0, 0.5, 0.0),
+                Arc::new(Lambertian::new(Vec3::ones())),
+            )),
+            30.0,
+        );
+        let o = Vec3::new(2.0, 1.5, 3.0);
+        for _ in 0..100 {
+            let dir = b.random(o);
+            assert!(b
+                .hit(&Ray::new(o, dir, 0.0), 0.001, f64::INFINITY)
+                .is_some());
+            assert!(b.pdf_value(&o, &dir) > 0.0);
+        }
+
+        let (n_theta, n_phi) = (400, 800);
+        let mut total = 0.0;
+        for i in 0..n_theta {
+            let theta = std::f64::consts::PI * (i as f64 + 0.5) / n_theta as f64;
+            let d_omega = theta.sin()
+                * (std::f64::consts::PI / n_theta as f64)
+                * (2.0 * std::f64::consts::PI / n_phi as f64);
+            for j in 0..n_phi {
+                let phi = 2.0 * std::f64::consts::PI * (j as f64 + 0.5) / n_phi as f64;
+                let v = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                );
+                total += b.pdf_value(&o, &v) * d_omega;
+            }
+        }
+        assert!((total - 1.0).abs() < 0.02, "{}", total);
+    }
 }
@@ -1,176 +1,618 @@
-use std::sync::Arc;
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use crate::{
     aabb::AABB,
     hittable::{HitRecord, Hittable},
-    rtweekend::random_int,
+    sampler::sample_1d,
     Ray, Vec3,
 };
 
-pub struct BVHNODE {
-    pub left: Arc<dyn Hittable>,
-    pub right: Arc<dyn Hittable>,
-    pub box1: AABB,
+#[derive(Clone, Copy, Debug)]
+pub struct BvhOptions {
+    // Nodes with more primitives than this are always split.
+    pub max_leaf_size: usize,
+    // Candidate split planes per axis are taken between this many centroid bins.
+    pub bins: usize,
 }
 
-impl Hittable for BVHNODE {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        if !self.box1.hit(ray, t_min, t_max) {
-            //println!("1\n");
-            return None;
-        }
-        if let Some(rec1) = self.left.hit(ray, t_min, t_max) {
-            if let Some(rec2) = self.right.hit(ray, t_min, rec1.t) {
-                return Some(rec2);
-            } else {
-                return Some(rec1);
-            }
-        } else if let Some(rec2) = self.right.hit(ray, t_min, t_max) {
-            return Some(rec2);
+impl Default for BvhOptions {
+    fn default() -> Self {
+        Self {
+            max_leaf_size: 4,
+            bins: 16,
         }
-        //println!("2\n");
-        None
     }
-    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
-        Some(self.box1.clone())
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct BvhStats {
+    pub primitives: usize,
+    pub nodes: usize,
+    pub leaves: usize,
+    pub depth: usize,
+    pub min_leaf_size: usize,
+    pub max_leaf_size: usize,
+    // Expected cost of a random ray that hits the root box, counting one per node visited
+    // and one per primitive tested.
+    pub sah_cost: f64,
+    pub build_time: Duration,
+    // Read back from a mesh cache rather than built.
+    pub cached: bool,
+}
+
+impl fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} primitives, {} nodes ({} leaves), depth {}, leaf size {}..{} (mean {:.2}), \
+             SAH cost {:.2}, {} in {:.1} ms",
+            self.primitives,
+            self.nodes,
+            self.leaves,
+            self.depth,
+            self.min_leaf_size,
+            self.max_leaf_size,
+            self.primitives as f64 / self.leaves.max(1) as f64,
+            self.sah_cost,
+            if self.cached { "loaded" } else { "built" },
+            self.build_time.as_secs_f64() * 1000.0
+        )
     }
 }
 
-impl BVHNODE {
-    pub fn new(
-        src_objects: &[Arc<dyn Hittable>],
-        start: usize,
-        end: usize,
+// A bounding volume hierarchy built with the binned surface area heuristic. The nodes
+// are kept depth first in one array and the objects in leaf order, so a traversal only
+// chases indices and makes virtual calls for the objects it actually tests. Meshes use
+// the same nodes over their faces, without an object per face; see `TriangleMesh`.
+pub struct Bvh {
+    nodes: Vec<Node>,
+    objects: Vec<Arc<dyn Hittable>>,
+    areas: AreaCdf,
+    pub stats: BvhStats,
+}
+
+pub(crate) struct Node {
+    pub(crate) bbox: AABB,
+    // A leaf holds `objects[first..first + count]`. An interior node has a count of 0, its
+    // first child right after it and its second child at `first`.
+    pub(crate) first: usize,
+    pub(crate) count: usize,
+    // The split axis, used to visit the child nearer to the ray origin first.
+    pub(crate) axis: usize,
+}
+
+// Below this depth every split is a median split, so no path can outgrow the stack.
+const MEDIAN_DEPTH: usize = 64;
+const STACK_SIZE: usize = 128;
+
+impl Bvh {
+    pub fn new(objects: &[Arc<dyn Hittable>], time0: f64, time1: f64) -> Self {
+        Self::with_options(objects, time0, time1, &BvhOptions::default())
+    }
+
+    // Every object needs a bounding box.
+    pub fn with_options(
+        objects: &[Arc<dyn Hittable>],
         time0: f64,
         time1: f64,
+        options: &BvhOptions,
     ) -> Self {
-        //println!("{},{}",start,end);
-        let mut objects = src_objects.to_owned();
-        let axis = random_int(0, 10000) % 3;
-        let left;
-        let right;
-        let span = end - start;
-        let comparetor = match axis {
-            0 => Self::box_x_compare,
-            1 => Self::box_y_compare,
-            _ => Self::box_z_compare,
+        let start = Instant::now();
+        let boxes: Vec<AABB> = objects
+            .iter()
+            .map(|o| {
+                o.bounding_box(time0, time1)
+                    .expect("objects in a BVH need a bounding box")
+            })
+            .collect();
+        let (nodes, order) = build_nodes(&boxes, options);
+        let objects = order.iter().map(|i| objects[*i].clone()).collect();
+        let mut bvh = Self::from_nodes(nodes, objects);
+        bvh.stats.build_time = start.elapsed();
+        bvh
+    }
+
+    // `objects` must already be in the order the leaves index them.
+    fn from_nodes(nodes: Vec<Node>, objects: Vec<Arc<dyn Hittable>>) -> Self {
+        let stats = stats(&nodes, objects.len());
+        let areas = AreaCdf::new(objects.iter().map(|object| object.area()));
+        Self {
+            nodes,
+            objects,
+            areas,
+            stats,
+        }
+    }
+}
+
+// Running total of the primitives' areas in leaf order, to sample them by area.
+pub(crate) struct AreaCdf(Vec<f64>);
+
+impl AreaCdf {
+    pub(crate) fn new<I: Iterator<Item = f64>>(areas: I) -> Self {
+        let mut total = 0.0;
+        Self(
+            areas
+                .map(|area| {
+                    total += area.max(0.0);
+                    total
+                })
+                .collect(),
+        )
+    }
+
+    pub(crate) fn total(&self) -> f64 {
+        self.0.last().copied().unwrap_or(0.0)
+    }
+
+    // The chance `pick` returns `i`: in proportion to its area, or evenly when no
+    // primitive knows its area.
+    pub(crate) fn probability(&self, i: usize) -> f64 {
+        let total = self.total();
+        if total <= 0.0 {
+            return 1.0 / self.0.len() as f64;
+        }
+        let below = if i == 0 { 0.0 } else { self.0[i - 1] };
+        (self.0[i] - below) / total
+    }
+
+    // There must be at least one primitive.
+    pub(crate) fn pick(&self) -> usize {
+        let n = self.0.len();
+        let total = self.total();
+        let i = if total > 0.0 {
+            let r = sample_1d() * total;
+            self.0.partition_point(|a| *a <= r)
+        } else {
+            (sample_1d() * n as f64) as usize
         };
-        if span == 1 {
-            left = objects[start].clone();
-            right = objects[start].clone();
-        } else if span == 2 {
-            if comparetor(&objects[start], &objects[start + 1]) {
-                left = objects[start].clone();
-                right = objects[start + 1].clone();
+        i.min(n - 1)
+    }
+}
+
+// The closest hit along `r` among the primitives of the leaves it reaches, each tested by
+// `hit(index, t_max)`. Boxes beyond the closest hit so far are skipped along with
+// everything in them, and the child nearer to the ray origin is visited first.
+pub(crate) fn closest_hit<F>(
+    nodes: &[Node],
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+    mut hit: F,
+) -> Option<HitRecord>
+where
+    F: FnMut(usize, f64) -> Option<HitRecord>,
+{
+    if nodes.is_empty() {
+        return None;
+    }
+    let negative = [r.dir.x < 0.0, r.dir.y < 0.0, r.dir.z < 0.0];
+    let mut closest = t_max;
+    let mut result = None;
+    let mut stack = [0usize; STACK_SIZE];
+    let mut top = 0;
+    let mut i = 0;
+    loop {
+        let node = &nodes[i];
+        if node.bbox.hit(r, t_min, closest) {
+            if node.count > 0 {
+                for prim in node.first..node.first + node.count {
+                    if let Some(rec) = hit(prim, closest) {
+                        closest = rec.t;
+                        result = Some(rec);
+                    }
+                }
             } else {
-                left = objects[start + 1].clone();
-                right = objects[start].clone();
-            }
-        } else {
-            objects.sort_by(|a, b| {
-                let x = a.bounding_box(time0, time1).unwrap().minmum.get(axis);
-                let y = b.bounding_box(time0, time1).unwrap().minmum.get(axis);
-                x.partial_cmp(&y).unwrap()
-            });
-            //obj.sort_by(|a,b| comparetor(&**a,&**b));
-
-            let mid = start + span / 2;
-            left = Arc::new(BVHNODE::new(&objects, start, mid, time0, time1));
-            right = Arc::new(BVHNODE::new(&objects, mid, end, time0, time1));
-        }
-
-        // let box0 = left.bounding_box(time0, time1).unwrap();
-        // let box1 = right.bounding_box(time0, time1).unwrap();
-        if let Some(box_left) = left.bounding_box(time0, time1) {
-            if let Some(box_right) = right.bounding_box(time0, time1) {
-                let _box = AABB::surrounding_box(box_left, box_right);
-                return Self {
-                    left,
-                    right,
-                    box1: _box,
+                let (near, far) = if negative[node.axis] {
+                    (node.first, i + 1)
+                } else {
+                    (i + 1, node.first)
                 };
+                stack[top] = far;
+                top += 1;
+                i = near;
+                continue;
             }
         }
-        panic!("worinima");
-        // Self {
-        //     left,
-        //     right,
-        //     box1: AABB::surrounding_box(box0.clone(), box1.clone()),
-        // }
-        // let left: Arc<dyn Hittable>;
-        // let right: Arc<dyn Hittable>;
-        // if span == 1 {
-        //     left = objects.remove(0);
-        //     right = left.clone();
-        // } else if span == 2 {
-        //     left = objects.remove(0);
-        //     right = objects.remove(0);
-        // } else {
-        //     objects.sort_by(|a, b| {
-        //         let x = a.bounding_box(time0, time1).unwrap().minmum.get(axis);
-        //         let y = b.bounding_box(time0, time1).unwrap().maxmum.get(axis);
-        //         x.partial_cmp(&y).unwrap()
-        //     });
-        //     let mid = span / 2;
-        //     let (objectspre, objectssuf) = objects.split_at_mut(mid);
-        //     left = Arc::new(BVHNODE::new(objectspre.to_vec(), mid, time0, time1));
-        //     right = Arc::new(BVHNODE::new(objectssuf.to_vec(), span - mid, time0, time1));
-        // }
-        // let box0 = left.bounding_box(time0, time1).unwrap();
-        // let box1 = right.bounding_box(time0, time1).unwrap();
-        // Self {
-        //     left: left,
-        //     right: right,
-        //     box1: AABB::surrounding_box(box0, box1),
-        // }
-    }
-
-    pub fn box_x_compare(a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>) -> bool {
-        let box_a = AABB::new(Vec3::zero(), Vec3::zero());
-        let box_b = AABB::new(Vec3::zero(), Vec3::zero());
-        if let Some(_rec1) = a.bounding_box(0.0, 0.0) {
-        } else {
-            println!("no bounding box in bvh node constructor\n");
+        if top == 0 {
+            return result;
         }
-        if let Some(_rec1) = b.bounding_box(0.0, 0.0) {
-        } else {
-            println!("no bounding box in bvh node constructor\n");
+        top -= 1;
+        i = stack[top];
+    }
+}
+
+// Calls `f` with the index of every primitive in a leaf whose box `r` passes through,
+// not just the nearest hit, for sums over every surface along a line.
+pub(crate) fn visit<F: FnMut(usize)>(nodes: &[Node], r: &Ray, t_min: f64, t_max: f64, mut f: F) {
+    if nodes.is_empty() {
+        return;
+    }
+    let mut stack = [0usize; STACK_SIZE];
+    let mut top = 0;
+    let mut i = 0;
+    loop {
+        let node = &nodes[i];
+        if node.bbox.hit(r, t_min, t_max) {
+            if node.count > 0 {
+                (node.first..node.first + node.count).for_each(&mut f);
+            } else {
+                stack[top] = node.first;
+                top += 1;
+                i += 1;
+                continue;
+            }
+        }
+        if top == 0 {
+            return;
         }
+        top -= 1;
+        i = stack[top];
+    }
+}
 
-        box_a.minmum.x < box_b.minmum.x
+// Lays out a hierarchy over `boxes`. The second vector lists the boxes in the order the
+// leaves refer to them.
+pub(crate) fn build_nodes(boxes: &[AABB], options: &BvhOptions) -> (Vec<Node>, Vec<usize>) {
+    if boxes.is_empty() {
+        return (Vec::new(), Vec::new());
     }
+    let mut builder = Builder {
+        prims: boxes,
+        order: (0..boxes.len()).collect(),
+        nodes: Vec::new(),
+        options: BvhOptions {
+            max_leaf_size: options.max_leaf_size.max(1),
+            bins: options.bins.max(2),
+        },
+    };
+    builder.build(0, boxes.len(), 1);
+    (builder.nodes, builder.order)
+}
 
-    pub fn box_y_compare(a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>) -> bool {
-        let box_a = AABB::new(Vec3::zero(), Vec3::zero());
-        let box_b = AABB::new(Vec3::zero(), Vec3::zero());
-        if let Some(_rec1) = a.bounding_box(0.0, 0.0) {
-        } else {
-            println!("no bounding box in bvh node constructor\n");
+impl Hittable for Bvh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        closest_hit(&self.nodes, r, t_min, t_max, |i, t_max| {
+            self.objects[i].hit(r, t_min, t_max)
+        })
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
+        self.nodes.first().map(|node| node.bbox.clone())
+    }
+
+    // Points are spread over the objects by area, so a direction is as likely as every
+    // object it crosses put together, not only the nearest.
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
+        let mut pdf = 0.0;
+        visit(
+            &self.nodes,
+            &Ray::new(*o, *v, 0.0),
+            0.001,
+            f64::INFINITY,
+            |i| {
+                let p = self.areas.probability(i);
+                if p > 0.0 {
+                    pdf += p * self.objects[i].pdf_value(o, v);
+                }
+            },
+        );
+        pdf
+    }
+
+    fn random(&self, o: Vec3) -> Vec3 {
+        if self.objects.is_empty() {
+            return Vec3::new(1.0, 0.0, 0.0);
         }
-        if let Some(_rec1) = b.bounding_box(0.0, 0.0) {
-        } else {
-            println!("no bounding box in bvh node constructor\n");
+        self.objects[self.areas.pick()].random(o)
+    }
+
+    fn area(&self) -> f64 {
+        self.areas.total()
+    }
+
+    fn emitters(self: Arc<Self>) -> Vec<Arc<dyn Hittable>> {
+        self.objects
+            .iter()
+            .flat_map(|object| object.clone().emitters())
+            .collect()
+    }
+}
+
+const TRAVERSAL_COST: f64 = 1.0;
+const INTERSECTION_COST: f64 = 1.0;
+
+struct Builder<'a> {
+    prims: &'a [AABB],
+    order: Vec<usize>,
+    nodes: Vec<Node>,
+    options: BvhOptions,
+}
+
+impl<'a> Builder<'a> {
+    fn bounds(&self, start: usize, end: usize, f: impl Fn(&AABB) -> AABB) -> AABB {
+        let mut bbox = f(&self.prims[self.order[start]]);
+        for i in &self.order[start + 1..end] {
+            bbox = AABB::surrounding_box(bbox, f(&self.prims[*i]));
+        }
+        bbox
+    }
+
+    fn leaf(&mut self, bbox: AABB, start: usize, end: usize) {
+        self.nodes.push(Node {
+            bbox,
+            first: start,
+            count: end - start,
+            axis: 0,
+        });
+    }
+
+    // Appends the subtree over `order[start..end]`, its root first.
+    fn build(&mut self, start: usize, end: usize, depth: usize) {
+        let bbox = self.bounds(start, end, |b| b.clone());
+        let n = end - start;
+        if n == 1 {
+            return self.leaf(bbox, start, end);
         }
+        let centroids = self.bounds(start, end, |b| {
+            let c = b.centroid();
+            AABB::new(c, c)
+        });
+
+        // Sweep the bins of each axis for the cheapest split.
+        let bins = self.options.bins;
+        let bin_of = |c: f64, axis: i32| {
+            let lo = centroids.minmum.get(axis);
+            let extent = centroids.maxmum.get(axis) - lo;
+            (((c - lo) / extent * bins as f64) as usize).min(bins - 1)
+        };
+        let area = bbox.surface_area().max(1e-300);
+        let mut best: Option<(f64, i32, usize)> = None;
+        for axis in 0..3 {
+            if depth >= MEDIAN_DEPTH || centroids.maxmum.get(axis) <= centroids.minmum.get(axis) {
+                continue;
+            }
+            let mut counts = vec![0usize; bins];
+            let mut boxes: Vec<Option<AABB>> = vec![None; bins];
+            for i in &self.order[start..end] {
+                let b = &self.prims[*i];
+                let k = bin_of(b.centroid().get(axis), axis);
+                counts[k] += 1;
+                boxes[k] = Some(match boxes[k].take() {
+                    Some(acc) => AABB::surrounding_box(acc, b.clone()),
+                    None => b.clone(),
+                });
+            }
+            // right_cost[k] is the area-weighted count of bins k.. on the right side.
+            let mut right_cost = vec![0.0; bins];
+            let (mut acc, mut count): (Option<AABB>, usize) = (None, 0);
+            for k in (1..bins).rev() {
+                acc = union(acc, &boxes[k]);
+                count += counts[k];
+                right_cost[k] = acc.as_ref().map_or(0.0, |b| b.surface_area()) * count as f64;
+            }
+            let (mut acc, mut count): (Option<AABB>, usize) = (None, 0);
+            for k in 1..bins {
+                acc = union(acc, &boxes[k - 1]);
+                count += counts[k - 1];
+                if count == 0 || count == n {
+                    continue;
+                }
+                let left_cost = acc.as_ref().map_or(0.0, |b| b.surface_area()) * count as f64;
+                let cost = TRAVERSAL_COST + INTERSECTION_COST * (left_cost + right_cost[k]) / area;
+                let better = match best {
+                    Some((c, _, _)) => cost < c,
+                    None => true,
+                };
+                if better {
+                    best = Some((cost, axis, k));
+                }
+            }
+        }
+
+        let leaf_cost = INTERSECTION_COST * n as f64;
+        let (mid, axis) = match best {
+            Some((cost, _, _)) if n <= self.options.max_leaf_size && cost >= leaf_cost => {
+                return self.leaf(bbox, start, end);
+            }
+            Some((_, axis, split)) => {
+                let prims = self.prims;
+                let (left, right): (Vec<usize>, Vec<usize>) = self.order[start..end]
+                    .iter()
+                    .partition(|i| bin_of(prims[**i].centroid().get(axis), axis) < split);
+                let mid = start + left.len();
+                self.order[start..mid].copy_from_slice(&left);
+                self.order[mid..end].copy_from_slice(&right);
+                (mid, axis)
+            }
+            None if n <= self.options.max_leaf_size => return self.leaf(bbox, start, end),
+            // Every centroid is in the same place, so no plane can separate them, or the
+            // tree is too deep already: halve the objects along the widest axis.
+            None => {
+                let extent = centroids.maxmum - centroids.minmum;
+                let axis = if extent.x >= extent.y && extent.x >= extent.z {
+                    0
+                } else if extent.y >= extent.z {
+                    1
+                } else {
+                    2
+                };
+                let prims = self.prims;
+                let mid = start + n / 2;
+                self.order[start..end].select_nth_unstable_by(n / 2, |a, b| {
+                    let a = prims[*a].centroid().get(axis);
+                    let b = prims[*b].centroid().get(axis);
+                    a.partial_cmp(&b).unwrap()
+                });
+                (mid, axis)
+            }
+        };
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            bbox,
+            first: 0,
+            count: 0,
+            axis: axis as usize,
+        });
+        self.build(start, mid, depth + 1);
+        self.nodes[index].first = self.nodes.len();
+        self.build(mid, end, depth + 1);
+    }
+}
 
-        box_a.minmum.y < box_b.minmum.y
+// Checks nodes that did not come from `build_nodes`: every child must come after its
+// parent, the leaves must cover the objects once, and no path may outgrow the stack.
+pub(crate) fn validate_nodes(nodes: &[Node], primitives: usize) -> Result<(), String> {
+    if nodes.is_empty() {
+        return Err("no BVH nodes".to_string());
     }
-    pub fn box_z_compare(a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>) -> bool {
-        let box_a = AABB::new(Vec3::zero(), Vec3::zero());
-        let box_b = AABB::new(Vec3::zero(), Vec3::zero());
-        if let Some(_rec1) = a.bounding_box(0.0, 0.0) {
+    let mut covered = vec![false; primitives];
+    let mut stack = vec![(0, 1)];
+    while let Some((i, depth)) = stack.pop() {
+        let node = &nodes[i];
+        if depth > STACK_SIZE {
+            return Err("the BVH is too deep".to_string());
+        }
+        if node.count > 0 {
+            if node.first + node.count > primitives {
+                return Err(format!("BVH node {} points past the objects", i));
+            }
+            for c in &mut covered[node.first..node.first + node.count] {
+                if *c {
+                    return Err(format!("BVH node {} repeats objects", i));
+                }
+                *c = true;
+            }
         } else {
-            println!("no bounding box in bvh node constructor\n");
+            if node.first <= i + 1 || node.first >= nodes.len() || i + 1 >= nodes.len() {
+                return Err(format!("BVH node {} has bad children", i));
+            }
+            if node.axis > 2 {
+                return Err(format!("BVH node {} has a bad split axis", i));
+            }
+            stack.push((i + 1, depth + 1));
+            stack.push((node.first, depth + 1));
         }
-        if let Some(_rec1) = b.bounding_box(0.0, 0.0) {
+    }
+    if covered.iter().any(|c| !c) {
+        return Err("the BVH leaves do not cover every object".to_string());
+    }
+    Ok(())
+}
+
+pub(crate) fn stats(nodes: &[Node], primitives: usize) -> BvhStats {
+    if nodes.is_empty() {
+        return BvhStats::default();
+    }
+    let mut stats = BvhStats {
+        primitives,
+        nodes: nodes.len(),
+        min_leaf_size: usize::MAX,
+        ..BvhStats::default()
+    };
+    let root_area = nodes[0].bbox.surface_area().max(1e-300);
+    let mut stack = vec![(0, 1)];
+    while let Some((i, depth)) = stack.pop() {
+        stats.depth = stats.depth.max(depth);
+        let node = &nodes[i];
+        let area = node.bbox.surface_area() / root_area;
+        if node.count > 0 {
+            stats.leaves += 1;
+            stats.min_leaf_size = stats.min_leaf_size.min(node.count);
+            stats.max_leaf_size = stats.max_leaf_size.max(node.count);
+            stats.sah_cost += INTERSECTION_COST * node.count as f64 * area;
         } else {
-            println!("no bounding box in bvh node constructor\n");
+            stats.sah_cost += TRAVERSAL_COST * area;
+            stack.push((i + 1, depth + 1));
+            stack.push((node.first, depth + 1));
+        }
+    }
+    stats
+}
+
+fn union(acc: Option<AABB>, b: &Option<AABB>) -> Option<AABB> {
+    match (acc, b) {
+        (Some(a), Some(b)) => Some(AABB::surrounding_box(a, b.clone())),
+        (a, None) => a,
+        (None, b) => b.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hittable_list::HittableList, materia::Lambertian, sphere::Sphere, Vec3};
+
+    fn spheres(n: usize) -> Vec<Arc<dyn Hittable>> {
+        let mat = Arc::new(Lambertian::new(Vec3::ones()));
+        (0..n)
+            .map(|i| {
+                let center = Vec3::new((i * 37 % 100) as f64, (i * 11 % 7) as f64, 0.0);
+                Arc::new(Sphere::new(center, 0.25, mat.clone())) as Arc<dyn Hittable>
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_every_object_is_reachable() {
+        let objects = spheres(100);
+        for leaf in [1, 4, 16].iter() {
+            let options = BvhOptions {
+                max_leaf_size: *leaf,
+                ..BvhOptions::default()
+            };
+            let bvh = Bvh::with_options(&objects, 0.0, 1.0, &options);
+            assert!(bvh.stats.max_leaf_size <= *leaf);
+            for i in 0..100 {
+                let center = Vec3::new((i * 37 % 100) as f64, (i * 11 % 7) as f64, 0.0);
+                let r = Ray::new(
+                    center + Vec3::new(0.0, 0.0, 5.0),
+                    Vec3::new(0.0, 0.0, -1.0),
+                    0.0,
+                );
+                let rec = bvh
+                    .hit(&r, 0.001, f64::INFINITY)
+                    .expect("object lost by the BVH");
+                assert!((rec.t - 4.75).abs() < 1e-9);
+            }
         }
+    }
 
-        box_a.minmum.z < box_b.minmum.z
+    #[test]
+    fn test_closest_hit_matches_a_linear_search() {
+        let objects = spheres(100);
+        let mut list = HittableList::new();
+        for o in objects.iter() {
+            list.add(o.clone());
+        }
+        let bvh = Bvh::new(&objects, 0.0, 1.0);
+        // Rays in every direction, so both child orders get exercised.
+        for i in 0..500 {
+            let f = i as f64;
+            let orig = Vec3::new(50.0 + 60.0 * (f * 0.7).sin(), 3.0, 20.0 * (f * 1.3).cos());
+            let target = Vec3::new((i * 37 % 100) as f64, (i * 11 % 7) as f64, 0.0);
+            let r = Ray::new(orig, target - orig, 0.0);
+            let expected = list.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t);
+            assert_eq!(bvh.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t), expected);
+        }
     }
-    // pub fn new(list:&Hittable_list,time0:f64,time1:f64) -> Self{
-    //     Self{
 
-    //     }
-    // }
+    #[test]
+    fn test_stats() {
+        let bvh = Bvh::new(&spheres(100), 0.0, 1.0);
+        let s = &bvh.stats;
+        assert_eq!(s.primitives, 100);
+        assert_eq!(s.nodes, 2 * s.leaves - 1);
+        assert!(s.min_leaf_size >= 1 && s.max_leaf_size <= 4);
+        // Spheres spread along a line split cleanly, so the tree is close to balanced.
+        assert!(s.depth <= 12, "depth {}", s.depth);
+        assert!(s.sah_cost > 1.0 && s.sah_cost < 20.0, "cost {}", s.sah_cost);
+
+        let single = Bvh::new(&spheres(1), 0.0, 1.0);
+        assert_eq!((single.stats.nodes, single.stats.depth), (1, 1));
+    }
 }
@@ -0,0 +1,425 @@
+use std::{
+    cell::RefCell,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    aabb::AABB,
+    bvh::{validate_nodes, Node},
+    materia::Material,
+    matrix::Mat4,
+    mesh::{MeshData, TriangleMesh},
+    Vec3,
+};
+
+// Built meshes kept on disk, so a large model is parsed and its BVH built only once. An
+// entry is named after its source file and a key hashed from the source's bytes, the
+// placement and the format version, so an edited file or a new transform simply misses.
+// Entries that fail to read back are rebuilt and overwritten.
+//
+// The file is little endian: magic, version, key, the number of meshes, then for each
+// mesh its material name, vertex buffers, faces and BVH nodes, and last an FNV-1a hash
+// of everything before it.
+pub struct MeshCache {
+    dir: PathBuf,
+    warnings: RefCell<Vec<String>>,
+}
+
+const MAGIC: &[u8; 8] = b"RTMESHC\0";
+const VERSION: u32 = 1;
+
+// A mesh read back from the cache, waiting for its material.
+pub struct CachedMesh {
+    // Whatever the loader saved to find the material again, often its name.
+    pub material: String,
+    data: MeshData,
+    nodes: Vec<Node>,
+    read_time: Duration,
+}
+
+impl CachedMesh {
+    pub fn data(&self) -> &MeshData {
+        &self.data
+    }
+
+    pub fn into_mesh(self, material: Arc<dyn Material>) -> TriangleMesh {
+        let start = Instant::now();
+        let mut data = self.data;
+        data.mat_ptr = material;
+        let mut mesh = TriangleMesh::from_nodes(data, self.nodes);
+        let stats = &mut mesh.stats;
+        stats.cached = true;
+        stats.build_time = start.elapsed() + self.read_time;
+        mesh
+    }
+}
+
+impl MeshCache {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self {
+            dir: dir.into(),
+            warnings: RefCell::new(Vec::new()),
+        }
+    }
+
+    // `sources` are the bytes of the model and of any files it pulls in.
+    pub fn key(sources: &[&[u8]], transform: &Mat4) -> u64 {
+        let mut hash = Fnv::new();
+        hash.write(&VERSION.to_le_bytes());
+        for bytes in sources.iter() {
+            hash.write(&(bytes.len() as u64).to_le_bytes());
+            hash.write(bytes);
+        }
+        for x in transform.m.iter().flatten() {
+            hash.write(&x.to_bits().to_le_bytes());
+        }
+        hash.finish()
+    }
+
+    fn path(&self, source: &str, key: u64) -> PathBuf {
+        let stem = Path::new(source)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("mesh");
+        self.dir.join(format!("{}-{:016x}.meshcache", stem, key))
+    }
+
+    // The meshes saved for `source` under `key`, if there is a readable entry.
+    pub fn load(&self, source: &str, key: u64) -> Option<Vec<CachedMesh>> {
+        let path = self.path(source, key);
+        let start = Instant::now();
+        let bytes = fs::read(&path).ok()?;
+        match read(&bytes, key) {
+            Ok(mut meshes) => {
+                // Reading is shared out evenly, so the stats add up to the time spent.
+                let read_time = start.elapsed() / meshes.len().max(1) as u32;
+                for mesh in meshes.iter_mut() {
+                    mesh.read_time = read_time;
+                }
+                Some(meshes)
+            }
+            Err(e) => {
+                self.warn(format!("ignoring {}: {}", path.display(), e));
+                None
+            }
+        }
+    }
+
+    // Saves `meshes` with the material names to look them up by. Failing to write only
+    // costs the next run a rebuild, so it is a warning.
+    pub fn store(&self, source: &str, key: u64, meshes: &[(&TriangleMesh, &str)]) {
+        let path = self.path(source, key);
+        let mut out = Writer(MAGIC.to_vec());
+        out.u32(VERSION);
+        out.u64(key);
+        out.usize(meshes.len());
+        for (mesh, material) in meshes.iter() {
+            write_mesh(&mut out, mesh, material);
+        }
+        let mut hash = Fnv::new();
+        hash.write(&out.0);
+        out.u64(hash.finish());
+        // Written aside and renamed, so a crash never leaves half an entry behind.
+        let tmp = path.with_extension("tmp");
+        let result = fs::create_dir_all(&self.dir)
+            .and_then(|_| fs::write(&tmp, &out.0))
+            .and_then(|_| fs::rename(&tmp, &path));
+        if let Err(e) = result {
+            self.warn(format!("cannot write {}: {}", path.display(), e));
+        }
+    }
+
+    fn warn(&self, message: String) {
+        self.warnings.borrow_mut().push(message);
+    }
+
+    pub fn take_warnings(&self) -> Vec<String> {
+        self.warnings.replace(Vec::new())
+    }
+}
+
+fn write_mesh(out: &mut Writer, mesh: &TriangleMesh, material: &str) {
+    let data = &mesh.data;
+    out.usize(material.len());
+    out.0.extend_from_slice(material.as_bytes());
+    for buffer in [&data.positions, &data.normals, &data.colors].iter() {
+        out.usize(buffer.len());
+        for v in buffer.iter() {
+            out.vec3(*v);
+        }
+    }
+    out.usize(data.uvs.len());
+    for (u, v) in data.uvs.iter() {
+        out.f64(*u);
+        out.f64(*v);
+    }
+    out.usize(data.indices.len());
+    for face in data.indices.iter() {
+        for i in face.iter() {
+            out.usize(*i);
+        }
+    }
+    let nodes = &mesh.nodes;
+    out.usize(nodes.len());
+    for node in nodes.iter() {
+        out.vec3(node.bbox.minmum);
+        out.vec3(node.bbox.maxmum);
+        out.usize(node.first);
+        out.usize(node.count);
+        out.0.push(node.axis as u8);
+    }
+}
+
+fn read(bytes: &[u8], key: u64) -> Result<Vec<CachedMesh>, String> {
+    if bytes.len() < MAGIC.len() + 8 || &bytes[..MAGIC.len()] != MAGIC {
+        return Err("not a mesh cache".to_string());
+    }
+    let (body, checksum) = bytes.split_at(bytes.len() - 8);
+    let mut hash = Fnv::new();
+    hash.write(body);
+    let mut tail = Reader(checksum);
+    if tail.u64()? != hash.finish() {
+        return Err("checksum mismatch".to_string());
+    }
+    let mut input = Reader(&body[MAGIC.len()..]);
+    let version = input.u32()?;
+    if version != VERSION {
+        return Err(format!("version {}, expected {}", version, VERSION));
+    }
+    if input.u64()? != key {
+        return Err("saved for another source".to_string());
+    }
+    let count = input.usize()?;
+    let mut meshes = Vec::new();
+    for _ in 0..count {
+        meshes.push(read_mesh(&mut input)?);
+    }
+    if !input.0.is_empty() {
+        return Err("trailing bytes".to_string());
+    }
+    Ok(meshes)
+}
+
+fn read_mesh(input: &mut Reader) -> Result<CachedMesh, String> {
+    let len = input.usize()?;
+    let material = String::from_utf8(input.take(len)?.to_vec())
+        .map_err(|_| "material name is not UTF-8".to_string())?;
+    let mut buffers = Vec::new();
+    for _ in 0..3 {
+        let n = input.count(24)?;
+        buffers.push(
+            (0..n)
+                .map(|_| input.vec3())
+                .collect::<Result<Vec<_>, _>>()?,
+        );
+    }
+    let n = input.count(16)?;
+    let uvs = (0..n)
+        .map(|_| Ok((input.f64()?, input.f64()?)))
+        .collect::<Result<Vec<_>, String>>()?;
+    let n = input.count(24)?;
+    let indices = (0..n)
+        .map(|_| Ok([input.usize()?, input.usize()?, input.usize()?]))
+        .collect::<Result<Vec<_>, String>>()?;
+    let n = input.count(65)?;
+    let nodes = (0..n)
+        .map(|_| {
+            let bbox = AABB::new(input.vec3()?, input.vec3()?);
+            let first = input.usize()?;
+            let count = input.usize()?;
+            let axis = input.take(1)?[0] as usize;
+            Ok(Node {
+                bbox,
+                first,
+                count,
+                axis,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let colors = buffers.pop().unwrap();
+    let normals = buffers.pop().unwrap();
+    let positions = buffers.pop().unwrap();
+    // The loader supplies the real material.
+    let placeholder = Arc::new(crate::materia::Lambertian::new(Vec3::zero()));
+    let mut data = MeshData::new(positions, indices, placeholder);
+    data.normals = normals;
+    data.uvs = uvs;
+    data.colors = colors;
+    data.validate()?;
+    validate_nodes(&nodes, data.indices.len())?;
+    Ok(CachedMesh {
+        material,
+        data,
+        nodes,
+        read_time: Duration::default(),
+    })
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u32(&mut self, x: u32) {
+        self.0.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn u64(&mut self, x: u64) {
+        self.0.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn usize(&mut self, x: usize) {
+        self.u64(x as u64);
+    }
+
+    fn f64(&mut self, x: f64) {
+        self.0.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn vec3(&mut self, v: Vec3) {
+        self.f64(v.x);
+        self.f64(v.y);
+        self.f64(v.z);
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < n {
+            return Err("truncated".to_string());
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let mut b = [0; 4];
+        b.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(b))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        let mut b = [0; 8];
+        b.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(b))
+    }
+
+    fn usize(&mut self) -> Result<usize, String> {
+        let x = self.u64()?;
+        if x > usize::MAX as u64 {
+            return Err("count out of range".to_string());
+        }
+        Ok(x as usize)
+    }
+
+    // A count of records of `size` bytes each, checked against what is left so a bad
+    // count cannot make us allocate more than the file holds.
+    fn count(&mut self, size: usize) -> Result<usize, String> {
+        let n = self.usize()?;
+        if n > self.0.len() / size {
+            return Err("truncated".to_string());
+        }
+        Ok(n)
+    }
+
+    fn f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_bits(self.u64()?))
+    }
+
+    fn vec3(&mut self) -> Result<Vec3, String> {
+        Ok(Vec3::new(self.f64()?, self.f64()?, self.f64()?))
+    }
+}
+
+// 64-bit FNV-1a: stable across builds and platforms, unlike the std hashers.
+pub(crate) struct Fnv(u64);
+
+impl Fnv {
+    pub(crate) fn new() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for b in bytes.iter() {
+            self.0 = (self.0 ^ *b as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hittable::Hittable, materia::Lambertian, Ray};
+
+    fn grid() -> TriangleMesh {
+        let mut positions = Vec::new();
+        let mut indices = Vec::new();
+        for j in 0..=20 {
+            for i in 0..=20 {
+                positions.push(Vec3::new(i as f64, j as f64, ((i * j) % 3) as f64 * 0.1));
+            }
+        }
+        for j in 0..20 {
+            for i in 0..20 {
+                let a = j * 21 + i;
+                indices.push([a, a + 1, a + 22]);
+                indices.push([a, a + 22, a + 21]);
+            }
+        }
+        let mat = Arc::new(Lambertian::new(Vec3::ones()));
+        TriangleMesh::new(MeshData::new(positions, indices, mat)).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip_and_corruption() {
+        let dir = std::env::temp_dir().join("raytracer_cache_test");
+        let _ = fs::remove_dir_all(&dir);
+        let cache = MeshCache::new(&dir);
+        let key = MeshCache::key(&[b"grid"], &Mat4::identity());
+        assert!(cache.load("grid.obj", key).is_none());
+
+        let mesh = grid();
+        cache.store("grid.obj", key, &[(&mesh, "stone")]);
+        let mut loaded = cache.load("grid.obj", key).unwrap();
+        assert_eq!(loaded.len(), 1);
+        let entry = loaded.pop().unwrap();
+        assert_eq!(entry.material, "stone");
+        let copy = entry.into_mesh(Arc::new(Lambertian::new(Vec3::ones())));
+        assert!(copy.bvh_stats().cached);
+        assert_eq!(copy.bvh_stats().nodes, mesh.bvh_stats().nodes);
+        for i in 0..50 {
+            let f = i as f64;
+            let orig = Vec3::new(10.0 + 8.0 * (f * 0.9).sin(), 10.0 + 8.0 * f.cos(), 5.0);
+            let r = Ray::new(orig, Vec3::new(0.1 * (f * 2.1).cos(), 0.05, -1.0), 0.0);
+            let t = |m: &TriangleMesh| m.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t);
+            assert_eq!(t(&copy), t(&mesh));
+        }
+
+        // A different key misses, and a damaged entry is reported and ignored.
+        assert!(cache.load("grid.obj", key ^ 1).is_none());
+        let path = cache.path("grid.obj", key);
+        let mut bytes = fs::read(&path).unwrap();
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+        assert!(cache.load("grid.obj", key).is_none());
+        fs::write(&path, &bytes[..100]).unwrap();
+        assert!(cache.load("grid.obj", key).is_none());
+        let warnings = cache.take_warnings();
+        assert_eq!(warnings.len(), 2);
+        assert!(
+            warnings[0].ends_with("checksum mismatch"),
+            "{}",
+            warnings[0]
+        );
+    }
+}
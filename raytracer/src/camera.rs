@@ -1,8 +1,8 @@
 use std::f64::consts::PI;
 
-use crate::rtweekend::random_double2;
+use crate::ray::Ray;
+use crate::sampler::{sample_1d, sample_2d};
 use crate::vec3::Vec3;
-use crate::{ray::Ray, vec3::random_in_unit_disk};
 #[derive(Clone, Debug, PartialEq, Copy)]
 pub struct Camera {
     origin: Vec3,
@@ -59,12 +59,19 @@ impl Camera {
     }
 
     pub fn get_ray(self, s: f64, t: f64) -> Ray {
-        let rd = random_in_unit_disk() * self.lens_radius;
-        let offset = self.u * rd.x + self.v * rd.y;
+        let mut offset = Vec3::zero();
+        if self.lens_radius > 0.0 {
+            // Polar mapping rather than rejection, so stratified lens samples stay stratified.
+            let (r, phi) = sample_2d();
+            let r = self.lens_radius * r.sqrt();
+            let phi = 2.0 * PI * phi;
+            offset = self.u * (r * phi.cos()) + self.v * (r * phi.sin());
+        }
+        let time = self._time0 + (self._time1 - self._time0) * sample_1d();
         Ray::new(
             self.origin + offset,
             self.lower_left_corner + self.horizontal * s + self.vertical * t - self.origin - offset,
-            random_double2(self._time0, self._time1),
+            time,
         )
     }
 }
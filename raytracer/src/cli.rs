@@ -0,0 +1,306 @@
+use std::path::Path;
+
+use raytracer::{
+    aov::Aov,
+    framebuffer::{is_hdr_format, is_supported_format},
+    sampler::SamplerKind,
+    tonemap::{Operator, ToneMapping},
+};
+
+pub const USAGE: &str = "\
+Usage: raytracer [OPTIONS] [SCENE]
+
+Renders SCENE (a JSON or YAML scene file, default scenes/glass_box.json).
+
+Options:
+  -W, --width <N>      image width in pixels (default 800)
+  -H, --height <N>     image height in pixels (default: width / camera aspect_ratio)
+  -s, --spp <N>        samples per pixel (default 2000)
+  -d, --depth <N>      maximum ray bounce depth (default 50)
+  -o, --output <PATH>  output image, format taken from the extension (default output/test.png)
+      --hdr <PATH>     also write the linear radiance as .exr, .hdr or .pfm (repeatable)
+      --aov <PASS>=<PATH>
+                       also write a first-hit pass: albedo, normal, depth, position,
+                       object_id or uv (repeatable; raw values in .exr/.hdr/.pfm)
+      --tonemap <OP>   tone curve for 8-bit output: none, reinhard, reinhard-extended,
+                       aces or hable (default none)
+      --exposure <EV>  exposure adjustment in stops before tone mapping (default 0)
+      --white <L>      radiance mapped to white by reinhard-extended (default 4)
+      --pass-spp <N>   samples per pixel added by each progressive pass (default 16)
+      --snapshot <SECONDS>
+                       rewrite the outputs with the current estimate every SECONDS
+      --checkpoint <PATH>
+                       save the accumulated samples to PATH, after every pass or at each
+                       snapshot when --snapshot is given
+      --resume         continue from the --checkpoint file up to --spp samples
+                       (a stratified render only to the --spp it started with)
+      --sampler <NAME> independent, stratified, halton or sobol (default sobol)
+      --seed <N>       random seed; the same seed gives the same image (default 0)
+      --bvh-stats      print the build statistics of every BVH in the scene
+      --mesh-cache <DIR>
+                       keep built OBJ, PLY and STL meshes in DIR and reuse them while
+                       the files are unchanged
+  -t, --threads <N>    worker threads (default 16)
+  -j, --jobs <N>       number of row bands the image is split into, at most one per
+                       row (default 32)
+  -h, --help           print this message
+";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    pub scene: String,
+    pub width: usize,
+    pub height: Option<usize>,
+    pub samples_per_pixel: usize,
+    pub max_depth: i32,
+    pub output: String,
+    pub hdr_outputs: Vec<String>,
+    pub aov_outputs: Vec<(Aov, String)>,
+    pub tone_mapping: ToneMapping,
+    pub pass_samples: usize,
+    pub snapshot: Option<u64>,
+    pub checkpoint: Option<String>,
+    pub resume: bool,
+    pub sampler: SamplerKind,
+    pub seed: u64,
+    pub bvh_stats: bool,
+    pub mesh_cache: Option<String>,
+    pub threads: usize,
+    pub jobs: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            scene: "scenes/glass_box.json".to_string(),
+            width: 800,
+            height: None,
+            samples_per_pixel: 2000,
+            max_depth: 50,
+            output: "output/test.png".to_string(),
+            hdr_outputs: Vec::new(),
+            aov_outputs: Vec::new(),
+            tone_mapping: ToneMapping::default(),
+            pass_samples: 16,
+            snapshot: None,
+            checkpoint: None,
+            resume: false,
+            sampler: SamplerKind::Sobol,
+            seed: 0,
+            bvh_stats: false,
+            mesh_cache: None,
+            threads: 16,
+            jobs: 32,
+        }
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
+pub enum Command {
+    Render(Options),
+    Help,
+}
+
+impl Options {
+    pub fn image_height(&self, aspect_ratio: f64) -> usize {
+        match self.height {
+            Some(h) => h,
+            None => (self.width as f64 / aspect_ratio) as usize,
+        }
+    }
+
+    // No more bands than rows, so a small image works with the default --jobs.
+    pub fn jobs(&self, image_height: usize) -> usize {
+        self.jobs.min(image_height)
+    }
+
+    // The passes to render, each once even if it is written to several files.
+    pub fn aovs(&self) -> Vec<Aov> {
+        let mut aovs = Vec::new();
+        for (aov, _) in self.aov_outputs.iter() {
+            if !aovs.contains(aov) {
+                aovs.push(*aov);
+            }
+        }
+        aovs
+    }
+}
+
+// Accepts both "--spp 100" and "--spp=100".
+pub fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut opts = Options::default();
+    let mut scene = None;
+    let mut tonemap = "none".to_string();
+    let mut white = 4.0;
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            return Ok(Command::Help);
+        }
+        if !arg.starts_with('-') || arg == "-" {
+            if scene.is_some() {
+                return Err(format!("unexpected argument \"{}\"", arg));
+            }
+            scene = Some(arg);
+            continue;
+        }
+        let (name, inline) = match arg.find('=') {
+            Some(i) => (arg[..i].to_string(), Some(arg[i + 1..].to_string())),
+            None => (arg.clone(), None),
+        };
+        let mut value = || match inline.clone() {
+            Some(v) => Ok(v),
+            None => args
+                .next()
+                .ok_or_else(|| format!("option {} needs a value", name)),
+        };
+        match name.as_str() {
+            "-W" | "--width" => opts.width = positive(&name, &value()?)?,
+            "-H" | "--height" => opts.height = Some(positive(&name, &value()?)?),
+            "-s" | "--spp" => opts.samples_per_pixel = positive(&name, &value()?)?,
+            "-d" | "--depth" => opts.max_depth = positive(&name, &value()?)? as i32,
+            "-o" | "--output" => opts.output = value()?,
+            "--hdr" => opts.hdr_outputs.push(value()?),
+            "--aov" => opts.aov_outputs.push(aov_output(&value()?)?),
+            "--tonemap" => tonemap = value()?,
+            "--exposure" => opts.tone_mapping.exposure = number(&name, &value()?)?,
+            "--white" => white = number(&name, &value()?)?,
+            "--pass-spp" => opts.pass_samples = positive(&name, &value()?)?,
+            "--snapshot" => opts.snapshot = Some(positive(&name, &value()?)? as u64),
+            "--checkpoint" => opts.checkpoint = Some(value()?),
+            "--resume" => opts.resume = true,
+            "--sampler" => {
+                let v = value()?;
+                opts.sampler = SamplerKind::from_name(&v)
+                    .ok_or_else(|| format!("unknown sampler \"{}\"", v))?
+            }
+            "--seed" => {
+                let v = value()?;
+                opts.seed = v
+                    .parse()
+                    .map_err(|_| format!("option {} expects an integer, got \"{}\"", name, v))?
+            }
+            "--bvh-stats" => opts.bvh_stats = true,
+            "--mesh-cache" => opts.mesh_cache = Some(value()?),
+            "-t" | "--threads" => opts.threads = positive(&name, &value()?)?,
+            "-j" | "--jobs" => opts.jobs = positive(&name, &value()?)?,
+            _ => return Err(format!("unknown option \"{}\"", name)),
+        }
+    }
+    if let Some(scene) = scene {
+        opts.scene = scene;
+    }
+    if white <= 0.0 {
+        return Err("option --white expects a positive radiance".to_string());
+    }
+    opts.tone_mapping.operator = Operator::from_name(&tonemap, white)
+        .ok_or_else(|| format!("unknown tone mapping operator \"{}\"", tonemap))?;
+    validate(&opts)?;
+    Ok(Command::Render(opts))
+}
+
+fn positive(name: &str, value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!(
+            "option {} expects a positive integer, got \"{}\"",
+            name, value
+        )),
+    }
+}
+
+fn aov_output(value: &str) -> Result<(Aov, String), String> {
+    let (name, path) = match value.find('=') {
+        Some(i) => (&value[..i], &value[i + 1..]),
+        None => return Err(format!("option --aov expects PASS=PATH, got \"{}\"", value)),
+    };
+    let aov = Aov::from_name(name).ok_or_else(|| format!("unknown AOV pass \"{}\"", name))?;
+    Ok((aov, path.to_string()))
+}
+
+fn number(name: &str, value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(x) if x.is_finite() => Ok(x),
+        _ => Err(format!(
+            "option {} expects a number, got \"{}\"",
+            name, value
+        )),
+    }
+}
+
+fn validate(opts: &Options) -> Result<(), String> {
+    if opts.width < 2 {
+        return Err("image width must be at least 2".to_string());
+    }
+    if let Some(h) = opts.height {
+        if h < 2 {
+            return Err("image height must be at least 2".to_string());
+        }
+    }
+    if !is_supported_format(&opts.output) {
+        return Err(format!(
+            "cannot tell the image format of \"{}\" from its extension",
+            opts.output
+        ));
+    }
+    for path in opts.hdr_outputs.iter() {
+        if !is_hdr_format(path) {
+            return Err(format!("{} is not an .exr, .hdr or .pfm file", path));
+        }
+    }
+    for (_, path) in opts.aov_outputs.iter() {
+        if !is_supported_format(path) {
+            return Err(format!(
+                "cannot tell the image format of \"{}\" from its extension",
+                path
+            ));
+        }
+    }
+    if opts.resume && opts.checkpoint.is_none() {
+        return Err("--resume needs a --checkpoint file".to_string());
+    }
+    if !Path::new(&opts.scene).is_file() {
+        return Err(format!("scene file {} not found", opts.scene));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn test_help() {
+        assert!(matches!(parse(&["--spp", "4", "-h"]), Ok(Command::Help)));
+    }
+
+    #[test]
+    fn test_bad_values() {
+        assert!(parse(&["--spp", "0"]).is_err());
+        assert!(parse(&["--width=abc"]).is_err());
+        assert!(parse(&["--depth"]).is_err());
+        assert!(parse(&["--frobnicate", "1"]).is_err());
+        assert!(parse(&["-o", "output/test.nope"]).is_err());
+        assert!(parse(&["--hdr", "output/test.png"]).is_err());
+        assert!(parse(&["--tonemap", "filmic"]).is_err());
+        assert!(parse(&["--aov", "albedo"]).is_err());
+        assert!(parse(&["--resume"]).is_err());
+        assert!(parse(&["--aov", "shadow=output/shadow.png"]).is_err());
+        assert!(parse(&["--exposure", "bright"]).is_err());
+    }
+
+    #[test]
+    fn test_small_image_with_default_jobs() {
+        let scene = concat!(env!("CARGO_MANIFEST_DIR"), "/../scenes/cornell_box.json");
+        let opts = match parse(&["-W", "20", "-H", "12", scene]) {
+            Ok(Command::Render(opts)) => opts,
+            _ => panic!("a 20x12 image should render"),
+        };
+        assert_eq!(opts.jobs(12), 12);
+        assert_eq!(opts.jobs(100), 32);
+    }
+}
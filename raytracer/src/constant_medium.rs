@@ -10,7 +10,7 @@ use std::{
 use crate::{
     hittable::{HitRecord, Hittable},
     materia::{Material, Metal, ScatterRecord},
-    rtweekend::random_double,
+    rtweekend::random_double2,
     texture::{SolidColor, Texture},
     vec3::random_in_unit_sphere,
     Ray, Vec3,
@@ -23,13 +23,13 @@ pub struct ConstantMedium {
 }
 
 impl ConstantMedium {
-    // pub fn new(b: Arc<dyn Hittable>, d: f64, a: Arc<dyn Texture>) -> Self {
-    //     Self {
-    //         boundary: b,
-    //         neg_inv_density: -1.0 / d,
-    //         phase_function: Arc::new(isotropic::new1(a)),
-    //     }
-    // }
+    pub fn new(b: Arc<dyn Hittable>, d: f64, a: Arc<dyn Texture>) -> Self {
+        Self {
+            boundary: b,
+            neg_inv_density: -1.0 / d,
+            phase_function: Arc::new(Isotropic::new1(a)),
+        }
+    }
 
     pub fn new1(b: Arc<dyn Hittable>, d: f64, c: Vec3) -> Self {
         Self {
@@ -89,7 +89,9 @@ impl Hittable for ConstantMedium {
 
         let ray_length = r.dir.len();
         let distance_inside_boundary = (rec2.t - rec1.t) * ray_length;
-        let hit_distance = self.neg_inv_density * random_double(0.0, 100.0).log(E);
+        // From the per-sample random stream rather than the sampler: a ray may cross any
+        // number of media, so the draws don't fit a bounce's block of dimensions.
+        let hit_distance = self.neg_inv_density * random_double2(0.0, 1.0).log(E);
 
         if hit_distance > distance_inside_boundary {
             return None;
@@ -124,9 +126,9 @@ impl Isotropic {
         }
     }
 
-    // pub fn new1(a: Arc<dyn Texture>) -> Self {
-    //     Self { albedo: a }
-    // }
+    pub fn new1(a: Arc<dyn Texture>) -> Self {
+        Self { albedo: a }
+    }
 }
 
 impl Material for Isotropic {
@@ -136,7 +138,7 @@ impl Material for Isotropic {
         rec: &crate::hittable::HitRecord,
         attenuation: &mut Vec3,
         scattered: &mut crate::Ray,
-        _srec: &mut ScatterRecord,
+        srec: &mut ScatterRecord,
     ) -> bool {
         //scattered = &mut Ray::new(rec.p, random_in_unit_sphere(), r_in.time);
         scattered.orig = rec.p;
@@ -145,6 +147,11 @@ impl Material for Isotropic {
         attenuation.x = self.albedo.value(rec.u, rec.v, &rec.p).x;
         attenuation.y = self.albedo.value(rec.u, rec.v, &rec.p).y;
         attenuation.z = self.albedo.value(rec.u, rec.v, &rec.p).z;
+        // The integrator follows the scatter record; left at its default, the medium would
+        // continue every path as a zero-length ray from the origin.
+        srec.is_specular = true;
+        srec.specular_ray = *scattered;
+        srec.attenuation = *attenuation;
         true
     }
 
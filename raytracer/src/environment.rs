@@ -0,0 +1,265 @@
+use std::{f64::consts::PI, fs::File, io::BufReader, path::Path};
+
+use image::{codecs::hdr::HdrDecoder, GenericImageView};
+
+use crate::{sampler::sample_2d, Vec3};
+
+// Light arriving from infinitely far away, around the whole scene. Rays that miss every
+// object take `radiance` of their direction, and the shadow ray can aim at it through
+// `random`, whose density per unit solid angle is `pdf_value`.
+pub trait Environment: Send + Sync {
+    fn radiance(&self, dir: &Vec3) -> Vec3;
+    fn random(&self) -> Vec3;
+    fn pdf_value(&self, dir: &Vec3) -> f64;
+    // Luminance arriving from the whole sphere, the integral of `radiance` over directions.
+    fn power(&self) -> f64;
+}
+
+// An environment looked up by direction in an equirectangular (lat-long) image. The
+// mapping is the one `Sphere::get_sphere_uv` uses for planets, seen from the inside: the
+// top row is straight up and u runs once around the y axis. `rotation` turns the map
+// around y, in degrees.
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3>,
+    rotation: f64,
+    intensity: f64,
+    // Importance sampling over luminance * sin(theta): one distribution over the columns
+    // of every row, and one picking the row.
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl EnvironmentMap {
+    // Rows of `pixels` run top to bottom.
+    pub fn new(
+        width: usize,
+        height: usize,
+        pixels: Vec<Vec3>,
+        rotation: f64,
+        intensity: f64,
+    ) -> Self {
+        assert!(width > 0 && height > 0 && pixels.len() == width * height);
+        let rows: Vec<Distribution1D> = (0..height)
+            .map(|y| {
+                let sin_theta = ((y as f64 + 0.5) / height as f64 * PI).sin();
+                let func = pixels[y * width..(y + 1) * width]
+                    .iter()
+                    .map(|c| c.luminance().max(0.0) * sin_theta)
+                    .collect();
+                Distribution1D::new(func)
+            })
+            .collect();
+        let marginal = Distribution1D::new(rows.iter().map(|row| row.integral).collect());
+        Self {
+            width,
+            height,
+            pixels,
+            rotation: rotation.to_radians(),
+            intensity,
+            rows,
+            marginal,
+        }
+    }
+
+    // Radiance .hdr files are read as they are; other images the way `ImageTexture` reads
+    // them, as 8-bit values scaled to [0, 1].
+    pub fn load(file: &str, rotation: f64, intensity: f64) -> Result<Self, String> {
+        let err = |e: image::ImageError| format!("{}: {}", file, e);
+        let path = Path::new(file);
+        let is_hdr = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.eq_ignore_ascii_case("hdr"))
+            .unwrap_or(false);
+        if is_hdr {
+            let f = File::open(path).map_err(|e| format!("{}: {}", file, e))?;
+            let decoder = HdrDecoder::new(BufReader::new(f)).map_err(err)?;
+            let meta = decoder.metadata();
+            let pixels = decoder
+                .read_image_hdr()
+                .map_err(err)?
+                .iter()
+                .map(|p| Vec3::new(p[0] as f64, p[1] as f64, p[2] as f64))
+                .collect();
+            return Ok(Self::new(
+                meta.width as usize,
+                meta.height as usize,
+                pixels,
+                rotation,
+                intensity,
+            ));
+        }
+        let im = image::open(path).map_err(err)?;
+        let (width, height) = im.dimensions();
+        let pixels = im
+            .to_rgb8()
+            .pixels()
+            .map(|p| Vec3::new(p[0] as f64, p[1] as f64, p[2] as f64) / 255.0)
+            .collect();
+        Ok(Self::new(
+            width as usize,
+            height as usize,
+            pixels,
+            rotation,
+            intensity,
+        ))
+    }
+
+    fn uv(&self, dir: &Vec3) -> (f64, f64) {
+        let d = dir.unit();
+        let theta = d.y.clamp(-1.0, 1.0).acos();
+        let phi = (-d.z).atan2(d.x) + PI + self.rotation;
+        let u = (phi / (2.0 * PI)).rem_euclid(1.0);
+        (u, theta / PI)
+    }
+}
+
+impl Environment for EnvironmentMap {
+    fn radiance(&self, dir: &Vec3) -> Vec3 {
+        let (u, v) = self.uv(dir);
+        let x = ((u * self.width as f64) as usize).min(self.width - 1);
+        let y = ((v * self.height as f64) as usize).min(self.height - 1);
+        self.pixels[y * self.width + x] * self.intensity
+    }
+
+    // A direction drawn in proportion to how much light comes from it.
+    fn random(&self) -> Vec3 {
+        let (r1, r2) = sample_2d();
+        let (v, y) = self.marginal.sample(r1);
+        let (u, _) = self.rows[y].sample(r2);
+        lat_long_direction(u - self.rotation / (2.0 * PI), v)
+    }
+
+    fn pdf_value(&self, dir: &Vec3) -> f64 {
+        if self.marginal.integral <= 0.0 {
+            return 0.0;
+        }
+        let (u, v) = self.uv(dir);
+        let sin_theta = (v * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        let x = ((u * self.width as f64) as usize).min(self.width - 1);
+        let y = ((v * self.height as f64) as usize).min(self.height - 1);
+        // The image pdf over [0, 1]^2, then the Jacobian of the lat-long mapping.
+        let pdf = self.rows[y].func[x] / self.marginal.integral;
+        pdf / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn power(&self) -> f64 {
+        2.0 * PI * PI * self.marginal.integral * self.intensity
+    }
+}
+
+// The unit direction at (u, v) of an unrotated lat-long image.
+pub fn lat_long_direction(u: f64, v: f64) -> Vec3 {
+    let theta = v * PI;
+    let phi = u * 2.0 * PI - PI;
+    Vec3::new(
+        theta.sin() * phi.cos(),
+        theta.cos(),
+        -theta.sin() * phi.sin(),
+    )
+}
+
+// A piecewise-constant density over [0, 1] with one step per entry of `func`.
+struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+impl Distribution1D {
+    fn new(func: Vec<f64>) -> Self {
+        let n = func.len() as f64;
+        let mut cdf = Vec::with_capacity(func.len() + 1);
+        cdf.push(0.0);
+        for f in func.iter() {
+            cdf.push(cdf[cdf.len() - 1] + f / n);
+        }
+        let integral = cdf[cdf.len() - 1];
+        // Nothing to go by, so sample uniformly.
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0.0 {
+                *c / integral
+            } else {
+                i as f64 / n
+            };
+        }
+        Self {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    // A point in [0, 1) and the step it falls in.
+    fn sample(&self, r: f64) -> (f64, usize) {
+        let n = self.func.len();
+        // The last entry of the cdf not above `r`.
+        let i = match self.cdf.binary_search_by(|c| c.partial_cmp(&r).unwrap()) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        }
+        .min(n - 1);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let offset = if width > 0.0 {
+            (r - self.cdf[i]) / width
+        } else {
+            0.0
+        };
+        ((i as f64 + offset) / n as f64, i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One bright pixel in a dark map.
+    fn spot() -> EnvironmentMap {
+        let (w, h) = (16, 8);
+        let mut pixels = vec![Vec3::ones() * 0.01; w * h];
+        pixels[3 * w + 5] = Vec3::ones() * 50.0;
+        EnvironmentMap::new(w, h, pixels, 30.0, 2.0)
+    }
+
+    #[test]
+    fn test_sampling_matches_pdf() {
+        let env = spot();
+        // Samples land on the bright pixel and agree with its radiance.
+        let mut bright = 0;
+        for _ in 0..1000 {
+            let d = env.random();
+            assert!((d.len() - 1.0).abs() < 1e-9);
+            assert!(env.pdf_value(&d) > 0.0);
+            if env.radiance(&d).x > 99.0 {
+                bright += 1;
+            }
+        }
+        assert!(bright > 900, "{}", bright);
+
+        // The pdf integrates to one over the sphere, the radiance to `power`.
+        let n = 400;
+        let mut sum = 0.0;
+        let mut power = 0.0;
+        for i in 0..n {
+            for j in 0..2 * n {
+                let theta = (i as f64 + 0.5) / n as f64 * PI;
+                let phi = (j as f64 + 0.5) / (2 * n) as f64 * 2.0 * PI;
+                let d = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                let d_omega = theta.sin() * (PI / n as f64) * (PI / n as f64);
+                sum += env.pdf_value(&d) * d_omega;
+                power += env.radiance(&d).luminance() * d_omega;
+            }
+        }
+        assert!((sum - 1.0).abs() < 0.01, "{}", sum);
+        assert!((power / env.power() - 1.0).abs() < 0.01, "{}", power);
+    }
+}
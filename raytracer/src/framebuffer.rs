@@ -0,0 +1,184 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use image::{codecs::hdr::HdrEncoder, ImageBuffer, Rgb, RgbImage};
+
+use crate::{tonemap::ToneMapping, Vec3};
+
+// Linear radiance per pixel, row 0 at the top of the image.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Vec3::zero(); width * height],
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Vec3 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, c: Vec3) {
+        self.pixels[y * self.width + x] = c;
+    }
+
+    pub fn to_rgb_image(&self, tone_mapping: &ToneMapping) -> RgbImage {
+        let mut img: RgbImage = ImageBuffer::new(self.width as u32, self.height as u32);
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            *pixel = Rgb(tone_mapping.to_rgb8(self.get(x as usize, y as usize)));
+        }
+        img
+    }
+
+    // The format is picked from the extension: .exr, .hdr and .pfm keep the linear
+    // radiance, anything else is tone mapped by `to_rgb_image` and saved by the image crate.
+    pub fn save(&self, path: &str, tone_mapping: &ToneMapping) -> Result<(), String> {
+        let result = match extension(path).as_deref() {
+            Some("exr") => self.write_exr(path),
+            Some("hdr") => self.write_hdr(path),
+            Some("pfm") => self.write_pfm(path),
+            _ => self
+                .to_rgb_image(tone_mapping)
+                .save(path)
+                .map_err(|e| e.to_string()),
+        };
+        result.map_err(|e| format!("cannot write {}: {}", path, e))
+    }
+
+    pub fn write_pfm(&self, path: &str) -> Result<(), String> {
+        let mut w = create(path)?;
+        // A negative scale marks little-endian data; rows are stored bottom to top.
+        let mut out = format!("PF\n{} {}\n-1.0\n", self.width, self.height).into_bytes();
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let c = self.get(x, y);
+                for v in [c.x, c.y, c.z].iter() {
+                    out.extend_from_slice(&(*v as f32).to_le_bytes());
+                }
+            }
+        }
+        w.write_all(&out).map_err(|e| e.to_string())
+    }
+
+    pub fn write_hdr(&self, path: &str) -> Result<(), String> {
+        let w = create(path)?;
+        let data: Vec<Rgb<f32>> = self
+            .pixels
+            .iter()
+            .map(|c| Rgb([c.x as f32, c.y as f32, c.z as f32]))
+            .collect();
+        HdrEncoder::new(w)
+            .encode(&data, self.width, self.height)
+            .map_err(|e| e.to_string())
+    }
+
+    // Single-part scanline OpenEXR, uncompressed, 32-bit float B/G/R channels.
+    pub fn write_exr(&self, path: &str) -> Result<(), String> {
+        let mut w = create(path)?;
+        let mut out: Vec<u8> = Vec::new();
+        out.extend_from_slice(&20000630_i32.to_le_bytes());
+        out.extend_from_slice(&2_i32.to_le_bytes());
+
+        let mut channels = Vec::new();
+        for name in ["B", "G", "R"].iter() {
+            channels.extend_from_slice(name.as_bytes());
+            channels.push(0);
+            channels.extend_from_slice(&2_i32.to_le_bytes()); // FLOAT
+            channels.extend_from_slice(&[0, 0, 0, 0]); // pLinear + reserved
+            channels.extend_from_slice(&1_i32.to_le_bytes());
+            channels.extend_from_slice(&1_i32.to_le_bytes());
+        }
+        channels.push(0);
+        exr_attribute(&mut out, "channels", "chlist", &channels);
+        exr_attribute(&mut out, "compression", "compression", &[0]);
+        let mut window = Vec::new();
+        for v in [0, 0, self.width as i32 - 1, self.height as i32 - 1].iter() {
+            window.extend_from_slice(&v.to_le_bytes());
+        }
+        exr_attribute(&mut out, "dataWindow", "box2i", &window);
+        exr_attribute(&mut out, "displayWindow", "box2i", &window);
+        exr_attribute(&mut out, "lineOrder", "lineOrder", &[0]);
+        exr_attribute(
+            &mut out,
+            "pixelAspectRatio",
+            "float",
+            &1.0_f32.to_le_bytes(),
+        );
+        exr_attribute(&mut out, "screenWindowCenter", "v2f", &[0; 8]);
+        exr_attribute(
+            &mut out,
+            "screenWindowWidth",
+            "float",
+            &1.0_f32.to_le_bytes(),
+        );
+        out.push(0);
+
+        // One scanline per chunk, each chunk is y, byte count and the channel rows.
+        let line_bytes = self.width * 3 * 4;
+        let table_end = out.len() + self.height * 8;
+        for y in 0..self.height {
+            let offset = (table_end + y * (8 + line_bytes)) as u64;
+            out.extend_from_slice(&offset.to_le_bytes());
+        }
+        for y in 0..self.height {
+            out.extend_from_slice(&(y as i32).to_le_bytes());
+            out.extend_from_slice(&(line_bytes as i32).to_le_bytes());
+            for channel in 0..3 {
+                for x in 0..self.width {
+                    let c = self.get(x, y);
+                    let v = match channel {
+                        0 => c.z,
+                        1 => c.y,
+                        _ => c.x,
+                    };
+                    out.extend_from_slice(&(v as f32).to_le_bytes());
+                }
+            }
+        }
+        w.write_all(&out).map_err(|e| e.to_string())
+    }
+}
+
+fn exr_attribute(out: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    out.extend_from_slice(name.as_bytes());
+    out.push(0);
+    out.extend_from_slice(kind.as_bytes());
+    out.push(0);
+    out.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    out.extend_from_slice(value);
+}
+
+fn create(path: &str) -> Result<BufWriter<File>, String> {
+    File::create(path)
+        .map(BufWriter::new)
+        .map_err(|e| e.to_string())
+}
+
+fn extension(path: &str) -> Option<String> {
+    Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+}
+
+pub fn is_hdr_format(path: &str) -> bool {
+    matches!(
+        extension(path).as_deref(),
+        Some("exr") | Some("hdr") | Some("pfm")
+    )
+}
+
+pub fn is_supported_format(path: &str) -> bool {
+    is_hdr_format(path) || image::ImageFormat::from_path(path).is_ok()
+}
@@ -0,0 +1,345 @@
+use std::sync::Arc;
+
+use ::gltf::{buffer, image::Format, mesh::Mode, Document};
+use image::{DynamicImage, ImageBuffer};
+
+use crate::{
+    materia::{Dielectric, DiffuseLight, Lambertian, Material, Metal},
+    matrix::Mat4,
+    mesh::{MeshData, TriangleMesh},
+    texture::{ImageTexture, SolidColor, Texture},
+    Vec3,
+};
+
+// What a glTF file adds to a scene: every mesh primitive of every node of the default
+// scene, moved into place, and the first perspective camera if there is one.
+pub struct GltfScene {
+    pub meshes: Vec<TriangleMesh>,
+    pub camera: Option<GltfCamera>,
+}
+
+pub struct GltfCamera {
+    pub lookfrom: Vec3,
+    pub lookat: Vec3,
+    pub vup: Vec3,
+    pub vfov: f64,
+    pub aspect_ratio: Option<f64>,
+}
+
+// Loads a .gltf or .glb file, with the whole node hierarchy under `transform`. Primitives
+// take their glTF material unless `material` overrides it.
+pub fn load_gltf(
+    file: &str,
+    transform: &Mat4,
+    material: Option<Arc<dyn Material>>,
+) -> Result<GltfScene, String> {
+    let (document, buffers, images) =
+        ::gltf::import(file).map_err(|e| format!("cannot load {}: {}", file, e))?;
+    convert(&document, &buffers, &images, transform, material)
+        .map_err(|e| format!("{}: {}", file, e))
+}
+
+struct Converter<'a> {
+    buffers: &'a [buffer::Data],
+    images: &'a [::gltf::image::Data],
+    // Filled in on first use, indexed like the document's materials and images.
+    materials: Vec<Option<Arc<dyn Material>>>,
+    textures: Vec<Option<Arc<dyn Texture>>>,
+    material: Option<Arc<dyn Material>>,
+    scene: GltfScene,
+}
+
+fn convert(
+    document: &Document,
+    buffers: &[buffer::Data],
+    images: &[::gltf::image::Data],
+    transform: &Mat4,
+    material: Option<Arc<dyn Material>>,
+) -> Result<GltfScene, String> {
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or("the file has no scene")?;
+    let mut converter = Converter {
+        buffers,
+        images,
+        materials: vec![None; document.materials().len()],
+        textures: vec![None; images.len()],
+        material,
+        scene: GltfScene {
+            meshes: Vec::new(),
+            camera: None,
+        },
+    };
+    for node in scene.nodes() {
+        converter.node(&node, transform)?;
+    }
+    Ok(converter.scene)
+}
+
+impl<'a> Converter<'a> {
+    fn node(&mut self, node: &::gltf::Node, parent: &Mat4) -> Result<(), String> {
+        // glTF matrices are column-major.
+        let local = node.transform().matrix();
+        let mut m = Mat4::identity();
+        for (i, row) in m.m.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = local[j][i] as f64;
+            }
+        }
+        let world = *parent * m;
+
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                if let Some(data) = self.primitive(&primitive)? {
+                    let mut data = data;
+                    data.transform(&world)?;
+                    let name = mesh.name().unwrap_or("unnamed");
+                    let mesh =
+                        TriangleMesh::new(data).map_err(|e| format!("mesh {}: {}", name, e))?;
+                    self.scene.meshes.push(mesh);
+                }
+            }
+        }
+        if let (None, Some(camera)) = (&self.scene.camera, node.camera()) {
+            // Cameras look down -z with +y up in their node's space.
+            if let ::gltf::camera::Projection::Perspective(p) = camera.projection() {
+                self.scene.camera = Some(GltfCamera {
+                    lookfrom: world.transform_point(Vec3::zero()),
+                    lookat: world.transform_point(Vec3::new(0.0, 0.0, -1.0)),
+                    vup: world.transform_vector(Vec3::new(0.0, 1.0, 0.0)),
+                    vfov: (p.yfov() as f64).to_degrees(),
+                    aspect_ratio: p.aspect_ratio().map(|a| a as f64),
+                });
+            }
+        }
+        for child in node.children() {
+            self.node(&child, &world)?;
+        }
+        Ok(())
+    }
+
+    // Points and lines have no area to hit, so those primitives give None.
+    fn primitive(&mut self, primitive: &::gltf::Primitive) -> Result<Option<MeshData>, String> {
+        let buffers = self.buffers;
+        let reader = primitive.reader(|b| Some(&buffers[b.index()]));
+        let positions: Vec<Vec3> = reader
+            .read_positions()
+            .ok_or("a primitive has no positions")?
+            .map(|p| Vec3::new(p[0] as f64, p[1] as f64, p[2] as f64))
+            .collect();
+        let order: Vec<usize> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().map(|i| i as usize).collect(),
+            None => (0..positions.len()).collect(),
+        };
+        let indices: Vec<[usize; 3]> = match primitive.mode() {
+            Mode::Triangles => order.chunks_exact(3).map(|f| [f[0], f[1], f[2]]).collect(),
+            Mode::TriangleStrip => (2..order.len())
+                .map(|i| {
+                    if i % 2 == 0 {
+                        [order[i - 2], order[i - 1], order[i]]
+                    } else {
+                        [order[i - 1], order[i - 2], order[i]]
+                    }
+                })
+                .collect(),
+            Mode::TriangleFan => (2..order.len())
+                .map(|i| [order[0], order[i - 1], order[i]])
+                .collect(),
+            _ => return Ok(None),
+        };
+
+        let gltf_material = primitive.material();
+        let uv_set = gltf_material
+            .pbr_metallic_roughness()
+            .base_color_texture()
+            .map_or(0, |t| t.tex_coord());
+        let material = match (&self.material, gltf_material.index()) {
+            (Some(material), _) => material.clone(),
+            (None, Some(i)) => match &self.materials[i] {
+                Some(material) => material.clone(),
+                None => {
+                    let material = self.material(&gltf_material)?;
+                    self.materials[i] = Some(material.clone());
+                    material
+                }
+            },
+            (None, None) => Arc::new(Lambertian::new(Vec3::new(0.73, 0.73, 0.73))),
+        };
+
+        let mut data = MeshData::new(positions, indices, material);
+        if let Some(normals) = reader.read_normals() {
+            data.normals = normals
+                .map(|n| Vec3::new(n[0] as f64, n[1] as f64, n[2] as f64))
+                .collect();
+        }
+        if let Some(uvs) = reader.read_tex_coords(uv_set) {
+            // glTF puts v = 0 at the top of the image, ImageTexture at the bottom.
+            data.uvs = uvs
+                .into_f32()
+                .map(|t| (t[0] as f64, 1.0 - t[1] as f64))
+                .collect();
+        }
+        if let Some(colors) = reader.read_colors(0) {
+            data.colors = colors
+                .into_rgb_f32()
+                .map(|c| Vec3::new(c[0] as f64, c[1] as f64, c[2] as f64))
+                .collect();
+        }
+        Ok(Some(data))
+    }
+
+    // The metallic-roughness model has no direct match here, so this picks the closest:
+    //   emissive > 0       -> DiffuseLight(emissive texture and factor)
+    //   transmission > 0   -> Dielectric(ior)
+    //   metallic >= 0.5    -> Metal(base colour) with a fuzz of roughness squared
+    //   otherwise          -> Lambertian(base colour texture and factor)
+    fn material(&mut self, m: &::gltf::Material) -> Result<Arc<dyn Material>, String> {
+        let color = |c: &[f32]| Vec3::new(c[0] as f64, c[1] as f64, c[2] as f64);
+        let max = |c: Vec3| c.x.max(c.y).max(c.z);
+        let pbr = m.pbr_metallic_roughness();
+        let base = color(&pbr.base_color_factor());
+        let emissive = color(&m.emissive_factor());
+
+        if max(emissive) > 0.0 {
+            let texture = m.emissive_texture().map(|t| t.texture().source().index());
+            return Ok(Arc::new(DiffuseLight::new(self.tinted(texture, emissive)?)));
+        }
+        if m.transmission().map_or(0.0, |t| t.transmission_factor()) > 0.0 {
+            return Ok(Arc::new(Dielectric::new(m.ior().unwrap_or(1.5) as f64)));
+        }
+        if pbr.metallic_factor() >= 0.5 {
+            let roughness = pbr.roughness_factor() as f64;
+            return Ok(Arc::new(Metal::new(base, roughness * roughness)));
+        }
+        let texture = pbr
+            .base_color_texture()
+            .map(|t| t.texture().source().index());
+        Ok(Arc::new(Lambertian::new1(self.tinted(texture, base)?)))
+    }
+
+    fn tinted(&mut self, image: Option<usize>, factor: Vec3) -> Result<Arc<dyn Texture>, String> {
+        let i = match image {
+            Some(i) => i,
+            None => return Ok(Arc::new(SolidColor::new(factor))),
+        };
+        let texture = match &self.textures[i] {
+            Some(texture) => texture.clone(),
+            None => {
+                let texture: Arc<dyn Texture> =
+                    Arc::new(ImageTexture::from_image(to_image(&self.images[i])?));
+                self.textures[i] = Some(texture.clone());
+                texture
+            }
+        };
+        if factor == Vec3::ones() {
+            return Ok(texture);
+        }
+        Ok(Arc::new(Tinted { texture, factor }))
+    }
+}
+
+fn to_image(data: &::gltf::image::Data) -> Result<DynamicImage, String> {
+    let (w, h) = (data.width, data.height);
+    let mut pixels = data.pixels.clone();
+    if let Format::B8G8R8 | Format::B8G8R8A8 = data.format {
+        let n = if data.format == Format::B8G8R8 { 3 } else { 4 };
+        for p in pixels.chunks_exact_mut(n) {
+            p.swap(0, 2);
+        }
+    }
+    let image = match data.format {
+        Format::R8 => ImageBuffer::from_raw(w, h, pixels).map(DynamicImage::ImageLuma8),
+        Format::R8G8 => ImageBuffer::from_raw(w, h, pixels).map(DynamicImage::ImageLumaA8),
+        Format::R8G8B8 | Format::B8G8R8 => {
+            ImageBuffer::from_raw(w, h, pixels).map(DynamicImage::ImageRgb8)
+        }
+        Format::R8G8B8A8 | Format::B8G8R8A8 => {
+            ImageBuffer::from_raw(w, h, pixels).map(DynamicImage::ImageRgba8)
+        }
+        _ => return Err(format!("{:?} textures are not supported", data.format)),
+    };
+    image.ok_or_else(|| "an image has the wrong number of pixels".to_string())
+}
+
+// An image scaled by a colour factor, as glTF combines its textures and factors.
+struct Tinted {
+    texture: Arc<dyn Texture>,
+    factor: Vec3,
+}
+
+impl Texture for Tinted {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        Vec3::elementmul(self.texture.value(u, v, p), self.factor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hittable::Hittable, Ray};
+
+    // A binary glTF with an emissive triangle under a translated parent node, and a camera
+    // node at the origin turned to face +x.
+    fn glb() -> Vec<u8> {
+        let mut bin = Vec::new();
+        for p in [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]].iter() {
+            for x in p.iter() {
+                bin.extend_from_slice(&x.to_le_bytes());
+            }
+        }
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "scene": 0,
+            "scenes": [ { "nodes": [0, 2] } ],
+            "nodes": [
+                { "translation": [0, 0, -5], "children": [1] },
+                { "mesh": 0, "scale": [2, 2, 2] },
+                { "camera": 0, "rotation": [0, -0.70710678, 0, 0.70710678] }
+            ],
+            "meshes": [ { "primitives": [ { "attributes": { "POSITION": 0 }, "material": 0 } ] } ],
+            "materials": [ { "emissiveFactor": [2, 3, 4] } ],
+            "cameras": [ { "type": "perspective", "perspective": { "yfov": 0.5, "znear": 0.1 } } ],
+            "accessors": [ {
+                "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                "min": [0, 0, 0], "max": [1, 1, 0]
+            } ],
+            "bufferViews": [ { "buffer": 0, "byteLength": 36 } ],
+            "buffers": [ { "byteLength": 36 } ]
+        }"#;
+        let mut json = json.as_bytes().to_vec();
+        let padding = (4 - json.len() % 4) % 4;
+        json.resize(json.len() + padding, b' ');
+        let mut glb = b"glTF".to_vec();
+        glb.extend_from_slice(&2u32.to_le_bytes());
+        glb.extend_from_slice(&((12 + 8 + json.len() + 8 + bin.len()) as u32).to_le_bytes());
+        glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"JSON");
+        glb.extend_from_slice(&json);
+        glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"BIN\0");
+        glb.extend_from_slice(&bin);
+        glb
+    }
+
+    #[test]
+    fn test_hierarchy_material_and_camera() {
+        let (document, buffers, images) = ::gltf::import_slice(glb()).unwrap();
+        let placement = Mat4::translate(Vec3::new(0.0, 10.0, 0.0));
+        let scene = convert(&document, &buffers, &images, &placement, None).unwrap();
+        assert_eq!(scene.meshes.len(), 1);
+
+        // The triangle is scaled by 2, then moved to z = -5 and up by 10.
+        let r = Ray::new(Vec3::new(1.5, 10.4, 0.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let rec = scene.meshes[0].hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 5.0).abs() < 1e-9);
+        let emitted = rec.mat_ptr.emitted(&r, &rec, rec.u, rec.v, &rec.p);
+        assert_eq!(emitted, Vec3::new(2.0, 3.0, 4.0));
+
+        let camera = scene.camera.unwrap();
+        assert!((camera.lookfrom - Vec3::new(0.0, 10.0, 0.0)).len() < 1e-6);
+        assert!((camera.lookat - Vec3::new(1.0, 10.0, 0.0)).len() < 1e-6);
+        assert!((camera.vup - Vec3::new(0.0, 1.0, 0.0)).len() < 1e-6);
+        assert!((camera.vfov - 0.5f64.to_degrees()).abs() < 1e-4);
+    }
+}
@@ -13,6 +13,10 @@ pub struct HitRecord {
     pub front_face: bool,
     pub u: f64,
     pub v: f64,
+    // Set by `ObjectId`, 0 when the object was never tagged.
+    pub object_id: u32,
+    // Interpolated vertex colour on meshes that have one, white everywhere else.
+    pub color: Vec3,
 }
 
 impl HitRecord {
@@ -25,6 +29,8 @@ impl HitRecord {
             front_face: f,
             u: 0.0,
             v: 0.0,
+            object_id: 0,
+            color: Vec3::ones(),
         }
     }
     pub fn set_face_normal(&mut self, r: &Ray, outward_normal: Vec3) {
@@ -51,4 +57,66 @@ pub trait Hittable: Send + Sync {
     fn random(&self, _o: Vec3) -> Vec3 {
         Vec3::new(1.0, 0.0, 0.0)
     }
+
+    // Surface area, which groups pick their parts to sample by; zero means unknown.
+    fn area(&self) -> f64 {
+        0.0
+    }
+
+    // Estimated flux the object emits, see `Material::emitted_power`. Lights are picked in
+    // proportion to it; zero means unknown.
+    fn power(&self) -> f64 {
+        0.0
+    }
+
+    // The parts of this object with an emissive material, each placed in world space as an
+    // object of its own, so they can make up the light list. Wrappers wrap what their
+    // child returns; groups collect from every member.
+    fn emitters(self: Arc<Self>) -> Vec<Arc<dyn Hittable>> {
+        Vec::new()
+    }
+}
+
+// Tags every hit on `ptr` with an id, used by the object ID pass.
+pub struct ObjectId {
+    pub ptr: Arc<dyn Hittable>,
+    pub id: u32,
+}
+
+impl ObjectId {
+    pub fn new(p: Arc<dyn Hittable>, id: u32) -> Self {
+        Self { ptr: p, id }
+    }
+}
+
+impl Hittable for ObjectId {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut rec = self.ptr.hit(r, t_min, t_max)?;
+        rec.object_id = self.id;
+        Some(rec)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+        self.ptr.bounding_box(time0, time1)
+    }
+
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
+        self.ptr.pdf_value(o, v)
+    }
+
+    fn random(&self, o: Vec3) -> Vec3 {
+        self.ptr.random(o)
+    }
+
+    fn area(&self) -> f64 {
+        self.ptr.area()
+    }
+
+    fn power(&self) -> f64 {
+        self.ptr.power()
+    }
+
+    fn emitters(self: Arc<Self>) -> Vec<Arc<dyn Hittable>> {
+        self.ptr.clone().emitters()
+    }
 }
@@ -5,7 +5,7 @@ use std::sync::Arc;
 use crate::{
     aabb::AABB,
     hittable::{HitRecord, Hittable},
-    rtweekend::random_int,
+    sampler::sample_1d,
     vec3::Vec3,
     Ray,
 };
@@ -41,7 +41,8 @@ impl Hittable for HittableList {
     fn random(&self, _o: Vec3) -> Vec3 {
         let int_size = self.objects.len();
         //return self.objects[random_int(0, int_size as i32)as usize].random(_o);
-        self.objects[random_int(0, int_size as i32) as usize].random(_o)
+        let i = ((sample_1d() * int_size as f64) as usize).min(int_size - 1);
+        self.objects[i].random(_o)
     }
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
         let mut rec = None;
@@ -78,4 +79,15 @@ impl Hittable for HittableList {
         }
         Some(output)
     }
+
+    fn area(&self) -> f64 {
+        self.objects.iter().map(|object| object.area()).sum()
+    }
+
+    fn emitters(self: Arc<Self>) -> Vec<Arc<dyn Hittable>> {
+        self.objects
+            .iter()
+            .flat_map(|object| object.clone().emitters())
+            .collect()
+    }
 }
@@ -0,0 +1,134 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::AABB,
+    hittable::{HitRecord, Hittable},
+    materia::Material,
+    matrix::Mat4,
+    transform::Transform,
+    Ray, Vec3,
+};
+
+// One placement of shared geometry. The object, usually a mesh or a `Bvh` built once as
+// the bottom level, is referenced rather than copied, so many instances cost a matrix
+// each; a `Bvh` over the instances' boxes is the top level. `material`, when given,
+// replaces whatever the geometry was built with.
+pub struct Instance {
+    transform: Transform,
+    material: Option<Arc<dyn Material>>,
+}
+
+impl Instance {
+    pub fn new(
+        object: Arc<dyn Hittable>,
+        matrix: Mat4,
+        material: Option<Arc<dyn Material>>,
+    ) -> Result<Self, String> {
+        Ok(Self {
+            transform: Transform::new(object, matrix)?,
+            material,
+        })
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut rec = self.transform.hit(r, t_min, t_max)?;
+        if let Some(mat) = &self.material {
+            rec.mat_ptr = mat.clone();
+        }
+        Some(rec)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+        self.transform.bounding_box(time0, time1)
+    }
+
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
+        self.transform.pdf_value(o, v)
+    }
+
+    fn random(&self, o: Vec3) -> Vec3 {
+        self.transform.random(o)
+    }
+
+    fn area(&self) -> f64 {
+        self.transform.area()
+    }
+
+    // The override's emission over the object's area isn't known, so an emissive
+    // placement leaves its power unknown.
+    fn power(&self) -> f64 {
+        match &self.material {
+            Some(_) => 0.0,
+            None => self.transform.power(),
+        }
+    }
+
+    // An emissive override lights the whole placement; otherwise the object's own
+    // emitters are placed like it.
+    fn emitters(self: Arc<Self>) -> Vec<Arc<dyn Hittable>> {
+        match &self.material {
+            Some(mat) if mat.is_emissive() => vec![self],
+            Some(_) => Vec::new(),
+            None => {
+                let t = &self.transform;
+                Arc::new(Transform {
+                    ptr: t.ptr.clone(),
+                    matrix: t.matrix,
+                    inverse: t.inverse,
+                })
+                .emitters()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bvh::Bvh,
+        materia::{DiffuseLight, Lambertian},
+        sphere::Sphere,
+    };
+
+    #[test]
+    fn test_instances_share_geometry() {
+        let grey: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::ones()));
+        let spheres: Vec<Arc<dyn Hittable>> = (0..10)
+            .map(|i| {
+                let center = Vec3::new(i as f64, 0.0, 0.0);
+                Arc::new(Sphere::new(center, 0.4, grey.clone())) as Arc<dyn Hittable>
+            })
+            .collect();
+        let blas: Arc<dyn Hittable> = Arc::new(Bvh::new(&spheres, 0.0, 1.0));
+        let light: Arc<dyn Material> = Arc::new(DiffuseLight::new1(Vec3::new(2.0, 2.0, 2.0)));
+        let instances: Vec<Arc<dyn Hittable>> = (0..100)
+            .map(|i| {
+                let m = Mat4::translate(Vec3::new(0.0, 2.0 * i as f64, 0.0)) * Mat4::rotate_y(90.0);
+                let mat = if i % 2 == 0 {
+                    None
+                } else {
+                    Some(light.clone())
+                };
+                Arc::new(Instance::new(blas.clone(), m, mat).unwrap()) as Arc<dyn Hittable>
+            })
+            .collect();
+        assert_eq!(Arc::strong_count(&blas), 101);
+        let tlas = Bvh::new(&instances, 0.0, 1.0);
+
+        // Turned about y, the row of spheres runs along -z in every instance.
+        for i in 0..100 {
+            let orig = Vec3::new(5.0, 2.0 * i as f64, -7.0);
+            let r = Ray::new(orig, Vec3::new(-1.0, 0.0, 0.0), 0.0);
+            let rec = tlas.hit(&r, 0.001, f64::INFINITY).expect("instance missed");
+            assert!((rec.t - 4.6).abs() < 1e-9);
+            let emitted = rec.mat_ptr.emitted(&r, &rec, rec.u, rec.v, &rec.p);
+            let expected = if i % 2 == 0 { 0.0 } else { 2.0 };
+            assert_eq!(emitted, Vec3::new(expected, expected, expected));
+        }
+        let r = Ray::new(Vec3::new(5.0, 1.0, -7.0), Vec3::new(-1.0, 0.0, 0.0), 0.0);
+        assert!(tlas.hit(&r, 0.001, f64::INFINITY).is_none());
+    }
+}
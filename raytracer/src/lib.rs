@@ -0,0 +1,44 @@
+#[deny(clippy::float_cmp)]
+pub mod aabb;
+pub mod aarec;
+pub mod accumulator;
+pub mod aov;
+pub mod box_;
+pub mod bvh;
+pub mod cache;
+pub mod camera;
+pub mod constant_medium;
+pub mod environment;
+pub mod framebuffer;
+pub mod gltf;
+pub mod hittable;
+pub mod hittable_list;
+pub mod instance;
+pub mod light;
+pub mod light_tree;
+pub mod materia;
+pub mod matrix;
+pub mod mesh;
+pub mod obj;
+pub mod onb;
+pub mod pdf;
+pub mod perlin;
+pub mod ply;
+pub mod ray;
+pub mod render;
+pub mod rtweekend;
+pub mod sampler;
+pub mod scene;
+pub mod sky;
+pub mod sphere;
+pub mod stl;
+pub mod texture;
+pub mod tonemap;
+pub mod transform;
+
+#[allow(clippy::float_cmp)]
+pub mod vec3;
+
+pub use ray::Ray;
+pub use render::{color, RenderOutput, RenderSettings, Renderer};
+pub use vec3::Vec3;
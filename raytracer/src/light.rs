@@ -0,0 +1,172 @@
+use crate::Vec3;
+
+// Lights without a surface: a point, a spot and a distant sun. Rays can never hit them,
+// so the integrator asks each one for the light it sends towards a shading point and
+// traces a shadow ray to check nothing is in the way.
+pub trait Light: Send + Sync {
+    fn sample(&self, p: Vec3) -> Option<LightSample>;
+}
+
+pub struct LightSample {
+    // Unit vector from the shading point towards the light.
+    pub dir: Vec3,
+    // How far the shadow ray has to stay clear, infinite for a directional light.
+    pub distance: f64,
+    // Irradiance arriving at the point on a surface facing the light.
+    pub irradiance: Vec3,
+}
+
+// Radiates `intensity` watts per steradian in every direction, falling off with the
+// square of the distance.
+pub struct PointLight {
+    pub position: Vec3,
+    pub intensity: Vec3,
+}
+
+impl PointLight {
+    pub fn new(position: Vec3, intensity: Vec3) -> Self {
+        Self {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, p: Vec3) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance_squared = to_light.len_squared();
+        if distance_squared <= 0.0 {
+            return None;
+        }
+        let distance = distance_squared.sqrt();
+        Some(LightSample {
+            dir: to_light / distance,
+            distance,
+            irradiance: self.intensity / distance_squared,
+        })
+    }
+}
+
+// A point light limited to a cone around `direction`. Full intensity inside
+// `cone_angle - falloff` (half-angles in degrees), fading smoothly to nothing at
+// `cone_angle`.
+pub struct SpotLight {
+    pub position: Vec3,
+    pub direction: Vec3,
+    pub intensity: Vec3,
+    cos_outer: f64,
+    cos_inner: f64,
+}
+
+impl SpotLight {
+    pub fn new(
+        position: Vec3,
+        direction: Vec3,
+        intensity: Vec3,
+        cone_angle: f64,
+        falloff: f64,
+    ) -> Self {
+        let outer = cone_angle.clamp(0.0, 180.0).to_radians();
+        let inner = (cone_angle - falloff.max(0.0))
+            .max(0.0)
+            .to_radians()
+            .min(outer);
+        Self {
+            position,
+            direction: direction.unit(),
+            intensity,
+            cos_outer: outer.cos(),
+            cos_inner: inner.cos(),
+        }
+    }
+
+    fn falloff(&self, cos_theta: f64) -> f64 {
+        if cos_theta <= self.cos_outer {
+            return 0.0;
+        }
+        if cos_theta >= self.cos_inner {
+            return 1.0;
+        }
+        let t = (cos_theta - self.cos_outer) / (self.cos_inner - self.cos_outer);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, p: Vec3) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance_squared = to_light.len_squared();
+        if distance_squared <= 0.0 {
+            return None;
+        }
+        let distance = distance_squared.sqrt();
+        let dir = to_light / distance;
+        let scale = self.falloff(-(dir * self.direction));
+        if scale <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            dir,
+            distance,
+            irradiance: self.intensity * (scale / distance_squared),
+        })
+    }
+}
+
+// Parallel light travelling along `direction`, like the sun: the same `irradiance`
+// (watts per square metre, on a surface facing it) everywhere and no falloff.
+pub struct DirectionalLight {
+    pub direction: Vec3,
+    pub irradiance: Vec3,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Vec3) -> Self {
+        Self {
+            direction: direction.unit(),
+            irradiance,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _p: Vec3) -> Option<LightSample> {
+        Some(LightSample {
+            dir: -self.direction,
+            distance: f64::INFINITY,
+            irradiance: self.irradiance,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_falloff() {
+        let p = PointLight::new(Vec3::new(0.0, 4.0, 0.0), Vec3::new(8.0, 8.0, 8.0));
+        let s = p.sample(Vec3::zero()).unwrap();
+        assert_eq!(s.dir, Vec3::new(0.0, 1.0, 0.0));
+        assert!((s.distance - 4.0).abs() < 1e-12);
+        assert!((s.irradiance.x - 0.5).abs() < 1e-12);
+
+        let down = Vec3::new(0.0, -1.0, 0.0);
+        let spot = SpotLight::new(Vec3::new(0.0, 1.0, 0.0), down, Vec3::ones(), 30.0, 10.0);
+        let at = |deg: f64| {
+            let x = deg.to_radians().tan();
+            let s = spot.sample(Vec3::new(x, 0.0, 0.0));
+            s.map_or(0.0, |s| s.irradiance.x * (1.0 + x * x))
+        };
+        assert!((at(0.0) - 1.0).abs() < 1e-12);
+        assert!((at(19.0) - 1.0).abs() < 1e-12);
+        assert!(at(25.0) > 0.1 && at(25.0) < 0.9);
+        assert!(at(31.0).abs() < 1e-12);
+
+        let sun = DirectionalLight::new(Vec3::new(1.0, -1.0, 0.0), Vec3::ones());
+        let s = sun.sample(Vec3::new(5.0, 5.0, 5.0)).unwrap();
+        assert!((s.dir - Vec3::new(-1.0, 1.0, 0.0).unit()).len() < 1e-12);
+        assert!(s.distance.is_infinite());
+    }
+}
@@ -0,0 +1,259 @@
+use std::{cmp::Ordering, f64::consts::PI, sync::Arc};
+
+use crate::{aabb::AABB, hittable::Hittable, sampler::sample_1d, Ray, Vec3};
+
+// Deep enough for any median-split tree that fits in memory.
+const STACK_SIZE: usize = 64;
+
+// Picks one of many area lights for the shadow ray. The lights sit in a binary tree of
+// bounding boxes, each node knowing the power of the lights below it, and `random` walks
+// down from the root choosing a child in proportion to how much light it could send to
+// the shading point: its power over the squared distance to its box. Lights close by are
+// picked often and lights far away or dim rarely, yet every light keeps some chance, so
+// the estimate stays unbiased. Power comes from `Hittable::power`; lights whose power
+// isn't known count as the mean of the others.
+pub struct LightTree {
+    lights: Vec<Arc<dyn Hittable>>,
+    nodes: Vec<LightNode>,
+}
+
+struct LightNode {
+    bbox: AABB,
+    power: f64,
+    // A leaf holds `lights[light]`; an interior node its two children.
+    light: usize,
+    children: Option<[usize; 2]>,
+}
+
+impl LightTree {
+    // Lights are boxed over [0, 1], the span `RotateY` boxes its object in. Lights without a
+    // box are left out; `build_scene` warns about them.
+    pub fn new(lights: &[Arc<dyn Hittable>]) -> Self {
+        let lights: Vec<Arc<dyn Hittable>> = lights
+            .iter()
+            .filter(|light| light.bounding_box(0.0, 1.0).is_some())
+            .cloned()
+            .collect();
+        let powers: Vec<f64> = lights.iter().map(|l| l.power().max(0.0)).collect();
+        let known: Vec<f64> = powers.iter().copied().filter(|p| *p > 0.0).collect();
+        let fallback = if known.is_empty() {
+            1.0
+        } else {
+            known.iter().sum::<f64>() / known.len() as f64
+        };
+        let mut items: Vec<(usize, AABB, f64)> = lights
+            .iter()
+            .zip(powers.iter())
+            .enumerate()
+            .map(|(i, (light, power))| {
+                let bbox = light.bounding_box(0.0, 1.0).unwrap();
+                (i, bbox, if *power > 0.0 { *power } else { fallback })
+            })
+            .collect();
+        let mut nodes = Vec::with_capacity(2 * lights.len());
+        if !items.is_empty() {
+            build(&mut items, &mut nodes);
+        }
+        Self { lights, nodes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    // Luminance the lights send to `o`, integrated over the solid angle they cover, as the
+    // root's importance estimates it: flux is pi times area times radiance.
+    pub fn incident_power(&self, o: Vec3) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        self.nodes[0].importance(o) / PI
+    }
+
+    pub fn random(&self, o: Vec3) -> Vec3 {
+        let mut r = sample_1d();
+        let mut node = 0;
+        while let Some([left, right]) = self.nodes[node].children {
+            let p_left = self.left_probability(o, left, right);
+            // Reuse the sample within the child that was picked.
+            if r < p_left {
+                node = left;
+                r /= p_left;
+            } else {
+                node = right;
+                r = (r - p_left) / (1.0 - p_left);
+            }
+        }
+        self.lights[self.nodes[node].light].random(o)
+    }
+
+    // Sums over the lights the line (o, v) meets, each weighted by the chance `random`
+    // picks it from `o`.
+    pub fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let r = Ray::new(*o, *v, 0.0);
+        let mut pdf = 0.0;
+        let mut stack = [(0, 0.0); STACK_SIZE];
+        stack[0] = (0, 1.0);
+        let mut top = 1;
+        while top > 0 {
+            top -= 1;
+            let (node, prob) = stack[top];
+            let n = &self.nodes[node];
+            if !n.bbox.hit(&r, 0.0, f64::INFINITY) {
+                continue;
+            }
+            match n.children {
+                None => pdf += prob * self.lights[n.light].pdf_value(o, v),
+                Some([left, right]) => {
+                    let p_left = self.left_probability(*o, left, right);
+                    stack[top] = (left, prob * p_left);
+                    stack[top + 1] = (right, prob * (1.0 - p_left));
+                    top += 2;
+                }
+            }
+        }
+        pdf
+    }
+
+    fn left_probability(&self, o: Vec3, left: usize, right: usize) -> f64 {
+        let (a, b) = (
+            self.nodes[left].importance(o),
+            self.nodes[right].importance(o),
+        );
+        if a + b > 0.0 {
+            a / (a + b)
+        } else {
+            0.5
+        }
+    }
+}
+
+impl LightNode {
+    // Power over squared distance, which is held at the box's own size so a shading point
+    // next to or inside a cluster doesn't send all the samples to one side.
+    fn importance(&self, p: Vec3) -> f64 {
+        let half_diagonal = (self.bbox.maxmum - self.bbox.minmum) * 0.5;
+        let distance_squared = (p - self.bbox.centroid()).len_squared();
+        self.power / distance_squared.max(half_diagonal.len_squared())
+    }
+}
+
+// Splits at the median centroid along the longest axis, so the tree stays balanced
+// however the lights are spread. Returns the index of the subtree's root.
+fn build(items: &mut [(usize, AABB, f64)], nodes: &mut Vec<LightNode>) -> usize {
+    let bbox = items.iter().skip(1).fold(items[0].1.clone(), |b, item| {
+        AABB::surrounding_box(b, item.1.clone())
+    });
+    let power = items.iter().map(|item| item.2).sum();
+    let index = nodes.len();
+    nodes.push(LightNode {
+        bbox,
+        power,
+        light: items[0].0,
+        children: None,
+    });
+    if items.len() == 1 {
+        return index;
+    }
+
+    let mut min = items[0].1.centroid();
+    let mut max = min;
+    for item in items.iter() {
+        let c = item.1.centroid();
+        min = Vec3::new(min.x.min(c.x), min.y.min(c.y), min.z.min(c.z));
+        max = Vec3::new(max.x.max(c.x), max.y.max(c.y), max.z.max(c.z));
+    }
+    let extent = max - min;
+    let axis = (0..3)
+        .max_by(|a, b| {
+            extent
+                .get(*a)
+                .partial_cmp(&extent.get(*b))
+                .unwrap_or(Ordering::Equal)
+        })
+        .unwrap();
+    let mid = items.len() / 2;
+    items.select_nth_unstable_by(mid, |a, b| {
+        a.1.centroid()
+            .get(axis)
+            .partial_cmp(&b.1.centroid().get(axis))
+            .unwrap_or(Ordering::Equal)
+    });
+    let (left, right) = items.split_at_mut(mid);
+    let left = build(left, nodes);
+    let right = build(right, nodes);
+    nodes[index].children = Some([left, right]);
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        aarec::XzRect, hittable_list::HittableList, materia::DiffuseLight, texture::SolidColor,
+    };
+
+    fn lamp(x: f64, z: f64, brightness: f64) -> Arc<dyn Hittable> {
+        let mat = Arc::new(DiffuseLight::new(Arc::new(SolidColor::new(
+            Vec3::ones() * brightness,
+        ))));
+        Arc::new(XzRect::new(x, x + 1.0, z, z + 1.0, 4.0, mat))
+    }
+
+    #[test]
+    fn test_light_tree() {
+        let mut lights = Vec::new();
+        for i in 0..8 {
+            for j in 0..8 {
+                lights.push(lamp(2.0 + 3.0 * i as f64, 2.0 + 3.0 * j as f64, 1.0));
+            }
+        }
+        // One lamp right above the shading point, and one as far out as the grid's far
+        // corner but fifty times brighter.
+        lights.push(lamp(-0.5, -0.5, 1.0));
+        lights.push(lamp(23.0, 26.0, 50.0));
+        // An empty group has no box and is left out.
+        lights.push(Arc::new(HittableList::new()));
+        let tree = LightTree::new(&lights);
+        let o = Vec3::zero();
+
+        let (mut near, mut corner, mut bright) = (0, 0, 0);
+        for _ in 0..4000 {
+            let d = tree.random(o);
+            assert!(tree.pdf_value(&o, &d) > 0.0);
+            let p = o + d;
+            if p.x <= 0.5 && p.z <= 0.5 {
+                near += 1;
+            } else if p.x >= 23.0 && p.z >= 23.0 && p.z <= 24.0 {
+                corner += 1;
+            } else if p.z >= 26.0 {
+                bright += 1;
+            }
+        }
+        // The near lamp takes far more than its 1/66 share of the samples, and the bright
+        // lamp more than its dim neighbour.
+        assert!(near > 300, "{}", near);
+        assert!(bright > 10 * corner.max(1), "{} {}", bright, corner);
+
+        // The pdf integrates to one over the upper hemisphere, where the lamps are.
+        let n = 600;
+        let mut sum = 0.0;
+        for i in 0..n {
+            for j in 0..4 * n {
+                let theta = (i as f64 + 0.5) / n as f64 * PI / 2.0;
+                let phi = (j as f64 + 0.5) / (4 * n) as f64 * 2.0 * PI;
+                let d = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                let d_omega = theta.sin() * (PI / 2.0 / n as f64) * (2.0 * PI / (4 * n) as f64);
+                sum += tree.pdf_value(&o, &d) * d_omega;
+            }
+        }
+        assert!((sum - 1.0).abs() < 0.02, "{}", sum);
+    }
+}
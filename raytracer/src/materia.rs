@@ -6,12 +6,11 @@ use crate::hittable::HitRecord;
 use crate::onb::Onb;
 use crate::pdf::{CosinePdf, NonePdf, Pdf};
 use crate::ray::Ray;
-use crate::rtweekend::random_double;
+use crate::sampler::{sample_1d, sample_2d};
 use crate::texture::{SolidColor, Texture};
 use crate::vec3::{random_in_unit_sphere, reflect, refract, Vec3};
 pub fn random_cosine_direction() -> Vec3 {
-    let r1 = random_double(0.0, 100.0);
-    let r2 = random_double(0.0, 100.0);
+    let (r1, r2) = sample_2d();
     let z = (1.0 - r2).sqrt();
 
     let phi = 2.0 * PI * r1;
@@ -40,6 +39,22 @@ pub trait Material: Send + Sync {
     }
 
     fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: &Vec3) -> Vec3;
+
+    // Whether `emitted` can be anything but zero, which puts the surface in the light list.
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    // A rough mean of `emitted` over the surface, only used to weigh lights against each
+    // other.
+    fn average_emission(&self) -> Vec3 {
+        Vec3::zero()
+    }
+
+    // Estimated luminous flux of `area` of this material emitting on one side.
+    fn emitted_power(&self, area: f64) -> f64 {
+        PI * area * self.average_emission().luminance()
+    }
 }
 #[derive(Clone, Debug, PartialEq, Copy)]
 pub struct Metal {
@@ -154,9 +169,7 @@ impl Material for Lambertian {
         // attenuation.z = self.albedo.value(rec.u, rec.v, &rec.p).z;
 
         srec.is_specular = false;
-        srec.attenuation.x = self.albedo.value(rec.u, rec.v, &rec.p).x;
-        srec.attenuation.y = self.albedo.value(rec.u, rec.v, &rec.p).y;
-        srec.attenuation.z = self.albedo.value(rec.u, rec.v, &rec.p).z;
+        srec.attenuation = Vec3::elementmul(self.albedo.value(rec.u, rec.v, &rec.p), rec.color);
         srec.pdf_ptr = Arc::new(CosinePdf::new(&rec.normal));
         //*pdf = Vec3::dot(rec.normal.clone(),scattered.dir.clone()) / PI;
         true
@@ -233,7 +246,7 @@ impl Material for Dielectric {
         }
         let reflect_prob = schlick(cos_theta, etai_over_etat);
 
-        if random_double(0.0, 100.0) < reflect_prob {
+        if sample_1d() < reflect_prob {
             let reflected = reflect(unit_direction, rec.normal);
             srec.specular_ray.orig = rec.p;
             srec.specular_ray.dir = reflected;
@@ -261,9 +274,9 @@ pub struct DiffuseLight {
 }
 
 impl DiffuseLight {
-    // pub fn new(a: Arc<dyn Texture>) -> Self {
-    //     Self { emit: a.clone() }
-    // }
+    pub fn new(a: Arc<dyn Texture>) -> Self {
+        Self { emit: a }
+    }
 
     pub fn new1(c: Vec3) -> Self {
         Self {
@@ -281,6 +294,23 @@ impl Material for DiffuseLight {
         // Vec3::zero()
         self.emit.value(u, v, p)
     }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+
+    // Textures are sampled on a grid of (u, v) at the origin.
+    fn average_emission(&self) -> Vec3 {
+        let mut sum = Vec3::zero();
+        for i in 0..4 {
+            for j in 0..4 {
+                let (u, v) = ((i as f64 + 0.5) / 4.0, (j as f64 + 0.5) / 4.0);
+                sum += self.emit.value(u, v, &Vec3::zero());
+            }
+        }
+        sum / 16.0
+    }
+
     fn scatter(
         &self,
         _r_in: &Ray,
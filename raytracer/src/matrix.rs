@@ -0,0 +1,195 @@
+use std::ops::Mul;
+
+use crate::{camera::degrees_to_radians, Vec3};
+
+// Row-major 4x4 matrix acting on column vectors, so `a * b` applies `b` first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn identity() -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+        Self { m }
+    }
+
+    pub fn translate(offset: Vec3) -> Self {
+        let mut t = Self::identity();
+        t.m[0][3] = offset.x;
+        t.m[1][3] = offset.y;
+        t.m[2][3] = offset.z;
+        t
+    }
+
+    pub fn scale(s: Vec3) -> Self {
+        let mut t = Self::identity();
+        t.m[0][0] = s.x;
+        t.m[1][1] = s.y;
+        t.m[2][2] = s.z;
+        t
+    }
+
+    // Rotation by `angle` degrees around `axis` (right-handed).
+    pub fn rotate(axis: Vec3, angle: f64) -> Self {
+        let a = axis.unit();
+        let theta = degrees_to_radians(angle);
+        let (s, c) = (theta.sin(), theta.cos());
+        let mut t = Self::identity();
+        t.m[0][0] = a.x * a.x + (1.0 - a.x * a.x) * c;
+        t.m[0][1] = a.x * a.y * (1.0 - c) - a.z * s;
+        t.m[0][2] = a.x * a.z * (1.0 - c) + a.y * s;
+        t.m[1][0] = a.x * a.y * (1.0 - c) + a.z * s;
+        t.m[1][1] = a.y * a.y + (1.0 - a.y * a.y) * c;
+        t.m[1][2] = a.y * a.z * (1.0 - c) - a.x * s;
+        t.m[2][0] = a.x * a.z * (1.0 - c) - a.y * s;
+        t.m[2][1] = a.y * a.z * (1.0 - c) + a.x * s;
+        t.m[2][2] = a.z * a.z + (1.0 - a.z * a.z) * c;
+        t
+    }
+
+    pub fn rotate_x(angle: f64) -> Self {
+        Self::rotate(Vec3::new(1.0, 0.0, 0.0), angle)
+    }
+
+    pub fn rotate_y(angle: f64) -> Self {
+        Self::rotate(Vec3::new(0.0, 1.0, 0.0), angle)
+    }
+
+    pub fn rotate_z(angle: f64) -> Self {
+        Self::rotate(Vec3::new(0.0, 0.0, 1.0), angle)
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut t = Self::identity();
+        for i in 0..4 {
+            for j in 0..4 {
+                t.m[i][j] = self.m[j][i];
+            }
+        }
+        t
+    }
+
+    // Gauss-Jordan elimination with partial pivoting; None for singular matrices.
+    pub fn inverse(&self) -> Option<Self> {
+        let mut a = self.m;
+        let mut inv = Self::identity().m;
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|i, j| a[*i][col].abs().partial_cmp(&a[*j][col].abs()).unwrap())
+                .unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+            let p = a[col][col];
+            for j in 0..4 {
+                a[col][j] /= p;
+                inv[col][j] /= p;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let f = a[row][col];
+                    for j in 0..4 {
+                        a[row][j] -= f * a[col][j];
+                        inv[row][j] -= f * inv[col][j];
+                    }
+                }
+            }
+        }
+        Some(Self { m: inv })
+    }
+
+    // Determinant of the upper 3x3 block, the volume scale of the linear part.
+    pub fn linear_determinant(&self) -> f64 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        let m = &self.m;
+        let x = m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3];
+        let y = m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3];
+        let z = m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3];
+        let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+        if w == 1.0 {
+            Vec3::new(x, y, z)
+        } else {
+            Vec3::new(x / w, y / w, z / w)
+        }
+    }
+
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+
+    // Normals go through the inverse transpose; pass the inverse of the matrix that
+    // moves the points. The result is not normalised.
+    pub fn transform_normal(inverse: &Mat4, n: Vec3) -> Vec3 {
+        inverse.transpose().transform_vector(n)
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, other: Mat4) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Mat4 { m }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).len() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_compose_and_invert() {
+        let t = Mat4::translate(Vec3::new(1.0, 2.0, 3.0))
+            * Mat4::rotate_z(90.0)
+            * Mat4::scale(Vec3::new(2.0, 2.0, 2.0));
+        let p = t.transform_point(Vec3::new(1.0, 0.0, 0.0));
+        assert_near(p, Vec3::new(1.0, 4.0, 3.0));
+        let inv = t.inverse().unwrap();
+        assert_near(inv.transform_point(p), Vec3::new(1.0, 0.0, 0.0));
+        let id = t * inv;
+        for i in 0..4 {
+            for j in 0..4 {
+                let e = if i == j { 1.0 } else { 0.0 };
+                assert!((id.m[i][j] - e).abs() < 1e-12);
+            }
+        }
+        assert!((t.linear_determinant() - 8.0).abs() < 1e-12);
+        assert!(Mat4::scale(Vec3::new(1.0, 0.0, 1.0)).inverse().is_none());
+    }
+
+    #[test]
+    fn test_normal_stays_perpendicular() {
+        let t =
+            Mat4::scale(Vec3::new(4.0, 1.0, 1.0)) * Mat4::rotate(Vec3::new(1.0, 1.0, 0.0), 30.0);
+        let tangent = Vec3::new(1.0, -1.0, 0.0);
+        let normal = Vec3::new(1.0, 1.0, 0.0);
+        let n = Mat4::transform_normal(&t.inverse().unwrap(), normal);
+        assert!(Vec3::dot(n, t.transform_vector(tangent)).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,448 @@
+use std::{sync::Arc, time::Instant};
+
+use crate::{
+    aabb::AABB,
+    aarec::{max3, min3, sample_triangle},
+    bvh::{build_nodes, closest_hit, stats, visit, AreaCdf, BvhOptions, BvhStats, Node},
+    hittable::{HitRecord, Hittable},
+    materia::Material,
+    matrix::Mat4,
+    Ray, Vec3,
+};
+
+// Vertex buffers shared by every face of a mesh. `normals`, `uvs` and `colors` are either
+// empty or hold one entry per position.
+pub struct MeshData {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub colors: Vec<Vec3>,
+    pub indices: Vec<[usize; 3]>,
+    pub mat_ptr: Arc<dyn Material>,
+}
+
+impl MeshData {
+    pub fn new(positions: Vec<Vec3>, indices: Vec<[usize; 3]>, mat: Arc<dyn Material>) -> Self {
+        Self {
+            positions,
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            colors: Vec::new(),
+            indices,
+            mat_ptr: mat,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let n = self.positions.len();
+        if !self.normals.is_empty() && self.normals.len() != n {
+            return Err(format!("{} normals for {} vertices", self.normals.len(), n));
+        }
+        if !self.uvs.is_empty() && self.uvs.len() != n {
+            return Err(format!("{} uvs for {} vertices", self.uvs.len(), n));
+        }
+        if !self.colors.is_empty() && self.colors.len() != n {
+            return Err(format!("{} colors for {} vertices", self.colors.len(), n));
+        }
+        if let Some(face) = self.indices.iter().find(|f| f.iter().any(|i| *i >= n)) {
+            return Err(format!("face {:?} indexes past {} vertices", face, n));
+        }
+        Ok(())
+    }
+
+    // Moves the positions by `m` and the normals by its inverse transpose.
+    pub fn transform(&mut self, m: &Mat4) -> Result<(), String> {
+        let inverse = m
+            .inverse()
+            .ok_or_else(|| "the transform is singular".to_string())?;
+        for p in self.positions.iter_mut() {
+            *p = m.transform_point(*p);
+        }
+        for n in self.normals.iter_mut() {
+            *n = Mat4::transform_normal(&inverse, *n);
+        }
+        Ok(())
+    }
+}
+
+// A triangle mesh: the faces index into the shared `MeshData` and are kept in a BVH of
+// their own, whose leaves are ranges of `indices`. Faces are plain data tested by
+// `hit_face`, with no hittable object per face.
+pub struct TriangleMesh {
+    pub data: Arc<MeshData>,
+    pub(crate) nodes: Vec<Node>,
+    areas: AreaCdf,
+    pub(crate) stats: BvhStats,
+}
+
+impl TriangleMesh {
+    pub fn new(data: MeshData) -> Result<Self, String> {
+        Self::with_options(data, &BvhOptions::default())
+    }
+
+    pub fn with_options(mut data: MeshData, options: &BvhOptions) -> Result<Self, String> {
+        let start = Instant::now();
+        data.validate()?;
+        if data.indices.is_empty() {
+            return Err("mesh has no faces".to_string());
+        }
+        // The faces are put in the order the leaves refer to them, so the nodes alone
+        // describe the hierarchy and can be saved and read back as they are.
+        let boxes: Vec<AABB> = (0..data.indices.len())
+            .map(|face| face_box(&data, face))
+            .collect();
+        let (nodes, order) = build_nodes(&boxes, options);
+        data.indices = order.iter().map(|i| data.indices[*i]).collect();
+        let mut mesh = Self::from_nodes(data, nodes);
+        mesh.stats.build_time = start.elapsed();
+        Ok(mesh)
+    }
+
+    // `nodes` must refer to the faces of `data` in order.
+    pub(crate) fn from_nodes(data: MeshData, nodes: Vec<Node>) -> Self {
+        let areas = AreaCdf::new((0..data.indices.len()).map(|face| face_area(&data, face)));
+        Self {
+            stats: stats(&nodes, data.indices.len()),
+            data: Arc::new(data),
+            nodes,
+            areas,
+        }
+    }
+
+    pub fn bvh_stats(&self) -> &BvhStats {
+        &self.stats
+    }
+
+    fn vertices(&self, face: usize) -> [Vec3; 3] {
+        let [i0, i1, i2] = self.data.indices[face];
+        let p = &self.data.positions;
+        [p[i0], p[i1], p[i2]]
+    }
+
+    // Against the geometric normal: shading normals don't change where the face is.
+    fn face_pdf(&self, face: usize, o: &Vec3, v: &Vec3) -> f64 {
+        let area = face_area(&self.data, face);
+        if area <= 0.0 {
+            return 0.0;
+        }
+        let r = Ray::new(*o, *v, 0.0);
+        if let Some(rec) = hit_face(&self.data, face, &r, 0.001, f64::INFINITY) {
+            let [p0, p1, p2] = self.vertices(face);
+            let n = Vec3::cross(p1 - p0, p2 - p0).unit();
+            let distance_squared = rec.t * rec.t * v.squared_length();
+            let cosine = Vec3::dot(*v, n).abs() / v.len();
+            return distance_squared / (cosine * area);
+        }
+        0.0
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        closest_hit(&self.nodes, r, t_min, t_max, |face, t_max| {
+            hit_face(&self.data, face, r, t_min, t_max)
+        })
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
+        self.nodes.first().map(|node| node.bbox.clone())
+    }
+
+    // Points are spread uniformly over the whole surface, so a direction is as likely as
+    // every face it crosses put together, not only the nearest.
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
+        let mut pdf = 0.0;
+        visit(
+            &self.nodes,
+            &Ray::new(*o, *v, 0.0),
+            0.001,
+            f64::INFINITY,
+            |face| {
+                let p = self.areas.probability(face);
+                if p > 0.0 {
+                    pdf += p * self.face_pdf(face, o, v);
+                }
+            },
+        );
+        pdf
+    }
+
+    fn random(&self, o: Vec3) -> Vec3 {
+        let [p0, p1, p2] = self.vertices(self.areas.pick());
+        sample_triangle(p0, p1, p2) - o
+    }
+
+    fn area(&self) -> f64 {
+        self.areas.total()
+    }
+
+    fn power(&self) -> f64 {
+        self.data.mat_ptr.emitted_power(self.area())
+    }
+
+    fn emitters(self: Arc<Self>) -> Vec<Arc<dyn Hittable>> {
+        if self.data.mat_ptr.is_emissive() {
+            vec![self]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+// Watertight ray/triangle test (Woop, Benthin and Wald 2013): the vertices are moved into
+// a space where the ray runs along +z from the origin, so edges shared by two faces give
+// exactly opposite edge functions and no ray slips between them.
+fn hit_face(mesh: &MeshData, face: usize, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    let [i0, i1, i2] = mesh.indices[face];
+    let (p0, p1, p2) = (mesh.positions[i0], mesh.positions[i1], mesh.positions[i2]);
+    let d = r.dir;
+    let kz = if d.x.abs() > d.y.abs() {
+        if d.x.abs() > d.z.abs() {
+            0
+        } else {
+            2
+        }
+    } else if d.y.abs() > d.z.abs() {
+        1
+    } else {
+        2
+    };
+    let kx = (kz + 1) % 3;
+    let ky = (kx + 1) % 3;
+    let permute = |v: Vec3| Vec3::new(v.get(kx), v.get(ky), v.get(kz));
+    let d = permute(d);
+    let sx = -d.x / d.z;
+    let sy = -d.y / d.z;
+    let sz = 1.0 / d.z;
+    let shear = |p: Vec3| {
+        let p = permute(p - r.orig);
+        Vec3::new(p.x + sx * p.z, p.y + sy * p.z, p.z * sz)
+    };
+    let (a, b, c) = (shear(p0), shear(p1), shear(p2));
+
+    let e0 = b.x * c.y - b.y * c.x;
+    let e1 = c.x * a.y - c.y * a.x;
+    let e2 = a.x * b.y - a.y * b.x;
+    if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0) {
+        return None;
+    }
+    let det = e0 + e1 + e2;
+    if det == 0.0 {
+        return None;
+    }
+    let t = (e0 * a.z + e1 * b.z + e2 * c.z) / det;
+    if t < t_min || t > t_max {
+        return None;
+    }
+    let (b0, b1, b2) = (e0 / det, e1 / det, e2 / det);
+
+    let mut rec = HitRecord::new(
+        p0 * b0 + p1 * b1 + p2 * b2,
+        Vec3::zero(),
+        t,
+        mesh.mat_ptr.clone(),
+        false,
+    );
+    let (u, v) = if mesh.uvs.is_empty() {
+        (b1, b2)
+    } else {
+        let (uv0, uv1, uv2) = (mesh.uvs[i0], mesh.uvs[i1], mesh.uvs[i2]);
+        (
+            b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0,
+            b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1,
+        )
+    };
+    rec.u = u;
+    rec.v = v;
+    if !mesh.colors.is_empty() {
+        rec.color = mesh.colors[i0] * b0 + mesh.colors[i1] * b1 + mesh.colors[i2] * b2;
+    }
+    rec.set_face_normal(r, Vec3::cross(p1 - p0, p2 - p0).unit());
+    if !mesh.normals.is_empty() {
+        // Shading normal, kept on the side of the face the ray arrived from.
+        let n = mesh.normals[i0] * b0 + mesh.normals[i1] * b1 + mesh.normals[i2] * b2;
+        if n.squared_length() > 0.0 {
+            let n = n.unit();
+            rec.normal = if Vec3::dot(n, rec.normal) < 0.0 {
+                -n
+            } else {
+                n
+            };
+        }
+    }
+    Some(rec)
+}
+
+fn face_area(mesh: &MeshData, face: usize) -> f64 {
+    let [i0, i1, i2] = mesh.indices[face];
+    let (a, b, c) = (mesh.positions[i0], mesh.positions[i1], mesh.positions[i2]);
+    Vec3::cross(b - a, c - a).len() / 2.0
+}
+
+fn face_box(mesh: &MeshData, face: usize) -> AABB {
+    let [i0, i1, i2] = mesh.indices[face];
+    let (a, b, c) = (mesh.positions[i0], mesh.positions[i1], mesh.positions[i2]);
+    let pad = Vec3::new(0.0001, 0.0001, 0.0001);
+    AABB::new(
+        Vec3::new(
+            min3(a.x, b.x, c.x),
+            min3(a.y, b.y, c.y),
+            min3(a.z, b.z, c.z),
+        ) - pad,
+        Vec3::new(
+            max3(a.x, b.x, c.x),
+            max3(a.y, b.y, c.y),
+            max3(a.z, b.z, c.z),
+        ) + pad,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materia::Lambertian;
+
+    // Two triangles sharing the diagonal of the unit square in the z = 0 plane.
+    fn quad() -> MeshData {
+        MeshData::new(
+            vec![
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(1.0, 1.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+            ],
+            vec![[0, 1, 2], [0, 2, 3]],
+            Arc::new(Lambertian::new(Vec3::ones())),
+        )
+    }
+
+    #[test]
+    fn test_barycentrics_and_smooth_normal() {
+        let mut data = quad();
+        data.normals = vec![
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 1.0).unit(),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        ];
+        let mesh = TriangleMesh::new(data).unwrap();
+        let r = Ray::new(Vec3::new(0.75, 0.25, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let rec = mesh.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 1.0).abs() < 1e-12);
+        assert!(rec.front_face);
+        // p = 0.25 p0 + 0.5 p1 + 0.25 p2, so (u, v) = (b1, b2).
+        assert!((rec.u - 0.5).abs() < 1e-12 && (rec.v - 0.25).abs() < 1e-12);
+        assert!(rec.normal.x > 0.0 && rec.normal.z > 0.0);
+        assert!((rec.normal.len() - 1.0).abs() < 1e-12);
+
+        let back = Ray::new(Vec3::new(0.75, 0.25, -1.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let rec = mesh.hit(&back, 0.001, f64::INFINITY).unwrap();
+        assert!(!rec.front_face);
+        assert!(rec.normal.z < 0.0);
+    }
+
+    #[test]
+    fn test_shared_edge_is_watertight() {
+        let mesh = TriangleMesh::new(quad()).unwrap();
+        for i in 0..1000 {
+            let s = (i as f64 + 0.5) / 1000.0;
+            let o = Vec3::new(s, s, 0.0) + Vec3::new(0.3, -0.7, 1.0);
+            let r = Ray::new(o, Vec3::new(-0.3, 0.7, -1.0), 0.0);
+            assert!(
+                mesh.hit(&r, 0.001, f64::INFINITY).is_some(),
+                "missed at {}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn test_closest_hit_matches_every_face() {
+        // A bumpy 10x10 grid of quads, so rays cross several faces and leaves.
+        let mut positions = Vec::new();
+        for j in 0..11 {
+            for i in 0..11 {
+                let z = ((i * 7 + j * 3) % 5) as f64 * 0.1;
+                positions.push(Vec3::new(i as f64, j as f64, z));
+            }
+        }
+        let mut indices = Vec::new();
+        for j in 0..10 {
+            for i in 0..10 {
+                let k = j * 11 + i;
+                indices.push([k, k + 1, k + 12]);
+                indices.push([k, k + 12, k + 11]);
+            }
+        }
+        let mesh = TriangleMesh::new(MeshData::new(
+            positions,
+            indices,
+            Arc::new(Lambertian::new(Vec3::ones())),
+        ))
+        .unwrap();
+        for i in 0..300 {
+            let f = i as f64;
+            let o = Vec3::new(
+                5.0 + 8.0 * (f * 0.7).sin(),
+                5.0 + 8.0 * (f * 1.1).cos(),
+                3.0,
+            );
+            let target = Vec3::new((i % 10) as f64 + 0.3, (i / 30) as f64 + 0.6, 0.2);
+            let r = Ray::new(o, target - o, 0.0);
+            let expected = (0..mesh.data.indices.len())
+                .filter_map(|face| hit_face(&mesh.data, face, &r, 0.001, f64::INFINITY))
+                .map(|rec| rec.t)
+                .fold(None, |best: Option<f64>, t| {
+                    Some(best.map_or(t, |b| b.min(t)))
+                });
+            assert_eq!(
+                mesh.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_sampling_covers_a_closed_mesh() {
+        // A tetrahedron, seen from outside: lines through it cross two faces.
+        let mesh = TriangleMesh::new(MeshData::new(
+            vec![
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(2.0, 0.0, 0.0),
+                Vec3::new(0.0, 2.0, 0.0),
+                Vec3::new(0.0, 0.0, 2.0),
+            ],
+            vec![[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]],
+            Arc::new(Lambertian::new(Vec3::ones())),
+        ))
+        .unwrap();
+        assert!((mesh.area() - (6.0 + 2.0 * 3.0_f64.sqrt())).abs() < 1e-12);
+        let o = Vec3::new(3.0, 2.5, -2.0);
+        for _ in 0..100 {
+            let dir = mesh.random(o);
+            assert!(mesh
+                .hit(&Ray::new(o, dir, 0.0), 0.001, f64::INFINITY)
+                .is_some());
+            assert!(mesh.pdf_value(&o, &dir) > 0.0);
+        }
+
+        let (n_theta, n_phi) = (400, 800);
+        let mut total = 0.0;
+        for i in 0..n_theta {
+            let theta = std::f64::consts::PI * (i as f64 + 0.5) / n_theta as f64;
+            let d_omega = theta.sin()
+                * (std::f64::consts::PI / n_theta as f64)
+                * (2.0 * std::f64::consts::PI / n_phi as f64);
+            for j in 0..n_phi {
+                let phi = 2.0 * std::f64::consts::PI * (j as f64 + 0.5) / n_phi as f64;
+                let v = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                );
+                total += mesh.pdf_value(&o, &v) * d_omega;
+            }
+        }
+        assert!((total - 1.0).abs() < 0.02, "{}", total);
+    }
+}
@@ -0,0 +1,298 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    cache::{CachedMesh, MeshCache},
+    materia::{Dielectric, DiffuseLight, Lambertian, Material, Metal},
+    matrix::Mat4,
+    mesh::{MeshData, TriangleMesh},
+    texture::{ImageTexture, SolidColor, Texture},
+    Vec3,
+};
+
+// Loads an OBJ file as one mesh per group and material, with every vertex moved by
+// `transform`. Faces take their MTL material unless `material` overrides it; faces with
+// no material at all are light grey Lambertian.
+pub fn load_obj(
+    file: &str,
+    transform: &Mat4,
+    material: Option<Arc<dyn Material>>,
+) -> Result<Vec<TriangleMesh>, String> {
+    load_obj_cached(file, transform, material, None)
+}
+
+// As `load_obj`, reusing the meshes built by an earlier run when `cache` holds them. The
+// key covers the MTL files too, and the materials are made afresh from them by name.
+pub fn load_obj_cached(
+    file: &str,
+    transform: &Mat4,
+    material: Option<Arc<dyn Material>>,
+    cache: Option<&MeshCache>,
+) -> Result<Vec<TriangleMesh>, String> {
+    let cache = match cache {
+        Some(cache) => cache,
+        None => {
+            return Ok(build(file, transform, material)?
+                .into_iter()
+                .map(|(m, _)| m)
+                .collect())
+        }
+    };
+    let bytes = fs::read(file).map_err(|e| format!("cannot read {}: {}", file, e))?;
+    let dir = Path::new(file).parent().unwrap_or_else(|| Path::new(""));
+    let libraries = mtl_files(&bytes, dir);
+    let mut sources = vec![bytes];
+    // A missing library is left for tobj to report.
+    sources.extend(libraries.iter().filter_map(|path| fs::read(path).ok()));
+    let sources: Vec<&[u8]> = sources.iter().map(|b| b.as_slice()).collect();
+    let key = MeshCache::key(&sources, transform);
+
+    if let Some(entries) = cache.load(file, key) {
+        if let Some(meshes) = from_cache(file, entries, &material, &libraries)? {
+            return Ok(meshes);
+        }
+    }
+    let meshes = build(file, transform, material)?;
+    let named: Vec<(&TriangleMesh, &str)> = meshes.iter().map(|(m, n)| (m, n.as_str())).collect();
+    cache.store(file, key, &named);
+    Ok(meshes.into_iter().map(|(m, _)| m).collect())
+}
+
+// The meshes with the names of their MTL materials, empty for faces without one.
+fn build(
+    file: &str,
+    transform: &Mat4,
+    material: Option<Arc<dyn Material>>,
+) -> Result<Vec<(TriangleMesh, String)>, String> {
+    let (models, materials) = tobj::load_obj(
+        file,
+        &tobj::LoadOptions {
+            single_index: true,
+            triangulate: true,
+            ..Default::default()
+        },
+    )
+    .map_err(|e| format!("cannot load {}: {}", file, e))?;
+    let dir = Path::new(file).parent().unwrap_or_else(|| Path::new(""));
+    let names: Vec<String> = match &materials {
+        Ok(materials) => materials.iter().map(|m| m.name.clone()).collect(),
+        Err(_) => Vec::new(),
+    };
+    let materials: Vec<Arc<dyn Material>> = match material {
+        Some(_) => Vec::new(),
+        None => materials
+            .map_err(|e| format!("cannot load the materials of {}: {}", file, e))?
+            .iter()
+            .map(|m| convert_material(m, dir))
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("{}: {}", file, e))?,
+    };
+    let default = default_material();
+
+    let mut meshes = Vec::new();
+    for m in models.iter() {
+        let mesh = &m.mesh;
+        if mesh.indices.is_empty() {
+            continue;
+        }
+        let mat = match (&material, mesh.material_id) {
+            (Some(mat), _) => mat.clone(),
+            (None, Some(id)) => materials
+                .get(id)
+                .cloned()
+                .ok_or_else(|| format!("{}: group {} has no material {}", file, m.name, id))?,
+            (None, None) => default.clone(),
+        };
+        let name = mesh
+            .material_id
+            .and_then(|id| names.get(id).cloned())
+            .unwrap_or_default();
+        let vec3s = |v: &[f32]| -> Vec<Vec3> {
+            v.chunks(3)
+                .map(|p| Vec3::new(p[0] as f64, p[1] as f64, p[2] as f64))
+                .collect()
+        };
+        let mut data = MeshData::new(
+            vec3s(&mesh.positions),
+            mesh.indices
+                .chunks(3)
+                .map(|f| [f[0] as usize, f[1] as usize, f[2] as usize])
+                .collect(),
+            mat,
+        );
+        data.normals = vec3s(&mesh.normals);
+        data.uvs = mesh
+            .texcoords
+            .chunks(2)
+            .map(|t| (t[0] as f64, t[1] as f64))
+            .collect();
+        data.transform(transform)
+            .map_err(|e| format!("{}: {}", file, e))?;
+        let mesh = TriangleMesh::new(data).map_err(|e| format!("{}: {}: {}", file, m.name, e))?;
+        meshes.push((mesh, name));
+    }
+    if meshes.is_empty() {
+        return Err(format!("{} has no faces", file));
+    }
+    Ok(meshes)
+}
+
+// Gives cached meshes their materials back, or None if a name is no longer in the MTL
+// files and the OBJ has to be loaded again.
+fn from_cache(
+    file: &str,
+    entries: Vec<CachedMesh>,
+    material: &Option<Arc<dyn Material>>,
+    libraries: &[PathBuf],
+) -> Result<Option<Vec<TriangleMesh>>, String> {
+    let mut materials = HashMap::new();
+    if material.is_none() {
+        let dir = Path::new(file).parent().unwrap_or_else(|| Path::new(""));
+        for path in libraries.iter() {
+            let (mtl, _) = tobj::load_mtl(path)
+                .map_err(|e| format!("cannot load the materials of {}: {}", file, e))?;
+            for m in mtl.iter() {
+                let mat = convert_material(m, dir).map_err(|e| format!("{}: {}", file, e))?;
+                materials.insert(m.name.clone(), mat);
+            }
+        }
+    }
+    let default = default_material();
+    let mut meshes = Vec::new();
+    for entry in entries {
+        let mat = match (material, entry.material.as_str()) {
+            (Some(mat), _) => mat.clone(),
+            (None, "") => default.clone(),
+            (None, name) => match materials.get(name) {
+                Some(mat) => mat.clone(),
+                None => return Ok(None),
+            },
+        };
+        meshes.push(entry.into_mesh(mat));
+    }
+    Ok(Some(meshes))
+}
+
+// The libraries named by `mtllib`, which tobj looks for next to the OBJ.
+fn mtl_files(bytes: &[u8], dir: &Path) -> Vec<PathBuf> {
+    String::from_utf8_lossy(bytes)
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("mtllib"), Some(name)) => Some(dir.join(name)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn default_material() -> Arc<dyn Material> {
+    Arc::new(Lambertian::new(Vec3::new(0.73, 0.73, 0.73)))
+}
+
+// MTL has no notion of our material types, so this picks the closest one:
+//   Ke > 0                        -> DiffuseLight(Ke)
+//   d < 1 or illum 4, 6, 7        -> Dielectric(Ni)
+//   illum 3, or Ks brighter than Kd -> Metal(Ks) with a fuzz derived from Ns
+//   otherwise                     -> Lambertian(map_Kd, or Kd)
+pub fn convert_material(m: &tobj::Material, dir: &Path) -> Result<Arc<dyn Material>, String> {
+    let color = |c: [f32; 3]| Vec3::new(c[0] as f64, c[1] as f64, c[2] as f64);
+    let max = |c: Vec3| c.x.max(c.y).max(c.z);
+    let kd = color(m.diffuse);
+    let ks = color(m.specular);
+    let illum = m.illumination_model.unwrap_or(2);
+
+    if let Some(ke) = m.unknown_param.get("Ke") {
+        let ke =
+            parse_color(ke).ok_or_else(|| format!("material {}: bad Ke \"{}\"", m.name, ke))?;
+        if max(ke) > 0.0 {
+            return Ok(Arc::new(DiffuseLight::new1(ke)));
+        }
+    }
+    if m.dissolve < 1.0 || illum == 4 || illum == 6 || illum == 7 {
+        let ir = if m.optical_density > 0.0 {
+            m.optical_density as f64
+        } else {
+            1.5
+        };
+        return Ok(Arc::new(Dielectric::new(ir)));
+    }
+    if max(ks) > 0.0 && (illum == 3 || max(ks) > max(kd)) {
+        // Phong exponent to a roughness-like fuzz: Ns = 0 is fully fuzzy, large Ns sharp.
+        let fuzz = (2.0 / (m.shininess as f64 + 2.0)).sqrt().min(1.0);
+        return Ok(Arc::new(Metal::new(ks, fuzz)));
+    }
+    let albedo: Arc<dyn Texture> = if m.diffuse_texture.is_empty() {
+        Arc::new(SolidColor::new(kd))
+    } else {
+        let path = dir.join(&m.diffuse_texture);
+        let path = path.to_str().ok_or_else(|| {
+            format!(
+                "material {}: bad texture path {:?}",
+                m.name, m.diffuse_texture
+            )
+        })?;
+        Arc::new(ImageTexture::load(path).map_err(|e| format!("material {}: {}", m.name, e))?)
+    };
+    Ok(Arc::new(Lambertian::new1(albedo)))
+}
+
+fn parse_color(s: &str) -> Option<Vec3> {
+    let v: Vec<f64> = s
+        .split_whitespace()
+        .map(|x| x.parse().ok())
+        .collect::<Option<_>>()?;
+    match v.len() {
+        1 => Some(Vec3::new(v[0], v[0], v[0])),
+        3 => Some(Vec3::new(v[0], v[1], v[2])),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hittable::Hittable, Ray};
+    use std::fs;
+
+    #[test]
+    fn test_groups_get_their_materials() {
+        let dir = std::env::temp_dir().join("raytracer_obj_test");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("two.mtl"),
+            "newmtl lamp\nKd 0 0 0\nKe 4 4 4\n\nnewmtl red\nKd 0.8 0.1 0.1\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("two.obj"),
+            "mtllib two.mtl\n\
+             v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+             g lamp\nusemtl lamp\nf 1 2 3\n\
+             g red\nusemtl red\nf 1 3 4\n",
+        )
+        .unwrap();
+        let file = dir.join("two.obj");
+        let transform = Mat4::translate(Vec3::new(0.0, 0.0, -5.0)) * Mat4::rotate_x(180.0);
+        let meshes = load_obj(file.to_str().unwrap(), &transform, None).unwrap();
+        assert_eq!(meshes.len(), 2);
+
+        // Rotated to face -z and pushed back, so a ray down -z hits the back faces.
+        let r = Ray::new(Vec3::new(0.75, -0.25, 0.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let lamp = meshes[0].hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((lamp.t - 5.0).abs() < 1e-9);
+        assert!(!lamp.front_face);
+        let emitted = lamp.mat_ptr.emitted(&r, &lamp, lamp.u, lamp.v, &lamp.p);
+        assert_eq!(emitted, Vec3::new(4.0, 4.0, 4.0));
+        let r = Ray::new(Vec3::new(0.25, -0.75, 0.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let red = meshes[1].hit(&r, 0.001, f64::INFINITY).unwrap();
+        let emitted = red.mat_ptr.emitted(&r, &red, red.u, red.v, &red.p);
+        assert_eq!(emitted, Vec3::zero());
+        assert!(meshes[0].hit(&r, 0.001, f64::INFINITY).is_none());
+    }
+}
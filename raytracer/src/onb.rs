@@ -61,4 +61,25 @@ impl Hittable for FlipFace {
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<crate::aabb::AABB> {
         self.ptr.bounding_box(time0, time1)
     }
+
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
+        self.ptr.pdf_value(o, v)
+    }
+
+    fn random(&self, o: Vec3) -> Vec3 {
+        self.ptr.random(o)
+    }
+
+    fn area(&self) -> f64 {
+        self.ptr.area()
+    }
+
+    fn power(&self) -> f64 {
+        self.ptr.power()
+    }
+
+    // Which side faces out makes no difference to sampling.
+    fn emitters(self: Arc<Self>) -> Vec<Arc<dyn Hittable>> {
+        self.ptr.clone().emitters()
+    }
 }
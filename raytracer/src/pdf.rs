@@ -2,7 +2,7 @@ use std::{f64::consts::PI, sync::Arc};
 
 //use crate::{Vec3, hittable::Hittable, materia::random_cosine_direction, onb::Onb, rtweekend::random_double};
 use crate::{
-    hittable::Hittable, materia::random_cosine_direction, onb::Onb, rtweekend::random_double, Vec3,
+    hittable::Hittable, materia::random_cosine_direction, onb::Onb, sampler::sample_1d, Vec3,
 };
 pub trait Pdf {
     fn value(&self, direction: &Vec3) -> f64;
@@ -77,7 +77,7 @@ impl Pdf for MixturePdf {
     }
 
     fn generate(&self) -> Vec3 {
-        if random_double(0.0, 100.0) < 0.5 {
+        if sample_1d() < 0.5 {
             self.p0.generate()
         } else {
             self.p1.generate()
@@ -89,6 +89,7 @@ pub struct NonePdf {
 }
 
 impl NonePdf {
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         Self { val: 0.0 }
     }
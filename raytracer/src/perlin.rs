@@ -13,6 +13,7 @@ pub struct Perlin {
 }
 impl Perlin {
     #[allow(clippy::needless_range_loop)]
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         //let mut ran_fl = [0.0;POINT_COUNT];
         let mut ranv = [Vec3::zero(); POINT_COUNT];
@@ -0,0 +1,441 @@
+use std::{fs, sync::Arc};
+
+use crate::{
+    cache::MeshCache,
+    materia::{Lambertian, Material},
+    matrix::Mat4,
+    mesh::{MeshData, TriangleMesh},
+    Vec3,
+};
+
+// Loads a PLY file (ASCII or binary) as one mesh. Vertices need x, y and z and may carry
+// nx/ny/nz, red/green/blue and u/v (or s/t); faces are polygons, split into fans. Without
+// a material the mesh is white Lambertian tinted by the vertex colours, or light grey.
+pub fn load_ply(
+    file: &str,
+    transform: &Mat4,
+    material: Option<Arc<dyn Material>>,
+) -> Result<TriangleMesh, String> {
+    load_ply_cached(file, transform, material, None)
+}
+
+// As `load_ply`, reusing the mesh built by an earlier run when `cache` holds it.
+pub fn load_ply_cached(
+    file: &str,
+    transform: &Mat4,
+    material: Option<Arc<dyn Material>>,
+    cache: Option<&MeshCache>,
+) -> Result<TriangleMesh, String> {
+    let bytes = fs::read(file).map_err(|e| format!("cannot read {}: {}", file, e))?;
+    let key = MeshCache::key(&[&bytes], transform);
+    if let Some(cached) = cache
+        .and_then(|c| c.load(file, key))
+        .and_then(|mut v| v.pop())
+    {
+        let material =
+            material.unwrap_or_else(|| default_material(!cached.data().colors.is_empty()));
+        return Ok(cached.into_mesh(material));
+    }
+    let mut data = parse_ply(&bytes, material).map_err(|e| format!("{}: {}", file, e))?;
+    data.transform(transform)
+        .map_err(|e| format!("{}: {}", file, e))?;
+    let mesh = TriangleMesh::new(data).map_err(|e| format!("{}: {}", file, e))?;
+    if let Some(cache) = cache {
+        cache.store(file, key, &[(&mesh, "")]);
+    }
+    Ok(mesh)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "char" | "int8" => Self::I8,
+            "uchar" | "uint8" => Self::U8,
+            "short" | "int16" => Self::I16,
+            "ushort" | "uint16" => Self::U16,
+            "int" | "int32" => Self::I32,
+            "uint" | "uint32" => Self::U32,
+            "float" | "float32" => Self::F32,
+            "double" | "float64" => Self::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+
+    // Integer colour channels are scaled to [0, 1], float ones are taken as they are.
+    fn color_scale(self) -> f64 {
+        match self {
+            Self::U8 => 1.0 / 255.0,
+            Self::U16 => 1.0 / 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+enum Property {
+    Scalar(String, Scalar),
+    // A count of type .0 followed by that many items of type .1.
+    List(String, Scalar, Scalar),
+}
+
+impl Property {
+    fn name(&self) -> &str {
+        match self {
+            Property::Scalar(name, _) | Property::List(name, _, _) => name,
+        }
+    }
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+fn parse_header(bytes: &[u8]) -> Result<(Format, Vec<Element>, usize), String> {
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut pos = 0;
+    for line_no in 1.. {
+        let end = bytes[pos..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .ok_or("the header has no end_header line")?;
+        let line = std::str::from_utf8(&bytes[pos..pos + end])
+            .map_err(|_| format!("line {}: the header is not text", line_no))?
+            .trim_end_matches('\r');
+        pos += end + 1;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let bad = || format!("line {}: bad header line \"{}\"", line_no, line);
+        if line_no == 1 {
+            if line != "ply" {
+                return Err("not a PLY file".to_string());
+            }
+            continue;
+        }
+        match words.as_slice() {
+            ["format", f, "1.0"] => {
+                format = Some(match *f {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(format!("line {}: unknown format {}", line_no, f)),
+                });
+            }
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| bad())?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => {
+                let count = Scalar::from_name(count).ok_or_else(bad)?;
+                let item = Scalar::from_name(item).ok_or_else(bad)?;
+                elements
+                    .last_mut()
+                    .ok_or_else(bad)?
+                    .properties
+                    .push(Property::List(name.to_string(), count, item));
+            }
+            ["property", kind, name] => {
+                let kind = Scalar::from_name(kind).ok_or_else(bad)?;
+                elements
+                    .last_mut()
+                    .ok_or_else(bad)?
+                    .properties
+                    .push(Property::Scalar(name.to_string(), kind));
+            }
+            ["end_header"] => break,
+            _ => return Err(bad()),
+        }
+    }
+    let format = format.ok_or("the header has no format line")?;
+    Ok((format, elements, pos))
+}
+
+// Reads the values of the body one at a time, whatever the encoding.
+struct Body<'a> {
+    format: Format,
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Body<'a> {
+    fn read(&mut self, kind: Scalar) -> Option<f64> {
+        if self.format == Format::Ascii {
+            let rest = &self.bytes[self.pos..];
+            let start = rest.iter().position(|b| !b.is_ascii_whitespace())?;
+            let len = rest[start..]
+                .iter()
+                .position(|b| b.is_ascii_whitespace())
+                .unwrap_or(rest.len() - start);
+            self.pos += start + len;
+            return std::str::from_utf8(&rest[start..start + len])
+                .ok()?
+                .parse()
+                .ok();
+        }
+        let size = kind.size();
+        let mut b = [0u8; 8];
+        b[..size].copy_from_slice(self.bytes.get(self.pos..self.pos + size)?);
+        self.pos += size;
+        if self.format == Format::BinaryBigEndian {
+            b[..size].reverse();
+        }
+        Some(match kind {
+            Scalar::I8 => b[0] as i8 as f64,
+            Scalar::U8 => b[0] as f64,
+            Scalar::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+            Scalar::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+            Scalar::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::F64 => f64::from_le_bytes(b),
+        })
+    }
+
+    // Whether `n` more values of `kind` can still fit in the data. Every ASCII value takes
+    // at least a byte, so a corrupt count is caught before anything is allocated for it.
+    fn has_room(&self, n: usize, kind: Scalar) -> bool {
+        let size = if self.format == Format::Ascii {
+            1
+        } else {
+            kind.size()
+        };
+        match n.checked_mul(size) {
+            Some(len) => len <= self.bytes.len() - self.pos,
+            None => false,
+        }
+    }
+}
+
+pub fn parse_ply(bytes: &[u8], material: Option<Arc<dyn Material>>) -> Result<MeshData, String> {
+    let (format, elements, start) = parse_header(bytes)?;
+    let mut body = Body {
+        format,
+        bytes,
+        pos: start,
+    };
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut colors = Vec::new();
+    let mut indices = Vec::new();
+    for element in elements.iter() {
+        let find = |names: &[&str]| {
+            element.properties.iter().position(|p| match p {
+                Property::Scalar(name, _) => names.contains(&name.as_str()),
+                _ => false,
+            })
+        };
+        let position = [find(&["x"]), find(&["y"]), find(&["z"])];
+        let normal = [find(&["nx"]), find(&["ny"]), find(&["nz"])];
+        let uv = [
+            find(&["u", "s", "texture_u", "texture_s"]),
+            find(&["v", "t", "texture_v", "texture_t"]),
+        ];
+        let color = [find(&["red"]), find(&["green"]), find(&["blue"])];
+        let face = element
+            .properties
+            .iter()
+            .position(|p| p.name() == "vertex_indices" || p.name() == "vertex_index");
+        if element.name == "vertex" && position.iter().any(|p| p.is_none()) {
+            return Err("vertices have no x, y and z".to_string());
+        }
+        if element.name == "face" && face.is_none() {
+            return Err("faces have no vertex_indices".to_string());
+        }
+
+        let mut values = vec![0.0; element.properties.len()];
+        for i in 0..element.count {
+            let truncated = || format!("{} {}: unexpected end of data", element.name, i);
+            for (j, property) in element.properties.iter().enumerate() {
+                match property {
+                    Property::Scalar(_, kind) => {
+                        values[j] = body.read(*kind).ok_or_else(truncated)?;
+                    }
+                    Property::List(_, count, item) => {
+                        let n = body.read(*count).ok_or_else(truncated)? as usize;
+                        if !body.has_room(n, *item) {
+                            return Err(truncated());
+                        }
+                        let mut list = Vec::with_capacity(n);
+                        for _ in 0..n {
+                            list.push(body.read(*item).ok_or_else(truncated)?);
+                        }
+                        if element.name == "face" && face == Some(j) {
+                            if n < 3 {
+                                return Err(format!("face {} has {} vertices", i, n));
+                            }
+                            for k in 1..n - 1 {
+                                indices.push([
+                                    list[0] as usize,
+                                    list[k] as usize,
+                                    list[k + 1] as usize,
+                                ]);
+                            }
+                        }
+                    }
+                }
+            }
+            if element.name != "vertex" {
+                continue;
+            }
+            let get = |p: [Option<usize>; 3]| {
+                Vec3::new(
+                    values[p[0].unwrap()],
+                    values[p[1].unwrap()],
+                    values[p[2].unwrap()],
+                )
+            };
+            positions.push(get(position));
+            if normal.iter().all(|p| p.is_some()) {
+                normals.push(get(normal));
+            }
+            if let [Some(u), Some(v)] = uv {
+                uvs.push((values[u], values[v]));
+            }
+            if color.iter().all(|p| p.is_some()) {
+                let scale = match element.properties[color[0].unwrap()] {
+                    Property::Scalar(_, kind) => kind.color_scale(),
+                    _ => 1.0,
+                };
+                colors.push(get(color) * scale);
+            }
+        }
+    }
+    if format != Format::Ascii && body.pos != bytes.len() {
+        return Err(format!(
+            "{} bytes after the last element",
+            bytes.len() - body.pos
+        ));
+    }
+
+    let material = material.unwrap_or_else(|| default_material(!colors.is_empty()));
+    let mut data = MeshData::new(positions, indices, material);
+    data.normals = normals;
+    data.uvs = uvs;
+    data.colors = colors;
+    data.validate()?;
+    Ok(data)
+}
+
+// White when the vertex colours tint it, light grey otherwise.
+fn default_material(colored: bool) -> Arc<dyn Material> {
+    let albedo = if colored { 1.0 } else { 0.73 };
+    Arc::new(Lambertian::new(Vec3::new(albedo, albedo, albedo)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "element vertex 4\n\
+        property float x\nproperty float y\nproperty float z\n\
+        property uchar red\nproperty uchar green\nproperty uchar blue\n\
+        element face 1\nproperty list uchar int vertex_indices\nend_header\n";
+
+    fn check(data: &MeshData) {
+        assert_eq!(data.positions.len(), 4);
+        assert_eq!(data.positions[2], Vec3::new(1.0, 1.0, 0.0));
+        assert_eq!(data.indices, vec![[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(data.colors[1], Vec3::new(1.0, 0.0, 0.0));
+        assert!(data.normals.is_empty() && data.uvs.is_empty());
+    }
+
+    #[test]
+    fn test_ascii_and_binary_agree() {
+        let ascii = format!(
+            "ply\nformat ascii 1.0\ncomment a quad\n{}\
+             0 0 0 255 255 255\n1 0 0 255 0 0\n1 1 0 0 255 0\n0 1 0 0 0 255\n4 0 1 2 3\n",
+            HEADER
+        );
+        check(&parse_ply(ascii.as_bytes(), None).unwrap());
+
+        for (format, big) in [("binary_little_endian", false), ("binary_big_endian", true)].iter() {
+            let mut bytes = format!("ply\nformat {} 1.0\n{}", format, HEADER).into_bytes();
+            let vertices = [
+                [0.0f32, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 1.0, 0.0],
+            ];
+            let colors = [[255u8, 255, 255], [255, 0, 0], [0, 255, 0], [0, 0, 255]];
+            for (v, c) in vertices.iter().zip(colors.iter()) {
+                for x in v.iter() {
+                    let b = if *big {
+                        x.to_be_bytes()
+                    } else {
+                        x.to_le_bytes()
+                    };
+                    bytes.extend_from_slice(&b);
+                }
+                bytes.extend_from_slice(c);
+            }
+            bytes.push(4);
+            for i in 0..4i32 {
+                let b = if *big {
+                    i.to_be_bytes()
+                } else {
+                    i.to_le_bytes()
+                };
+                bytes.extend_from_slice(&b);
+            }
+            check(&parse_ply(&bytes, None).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_malformed_files() {
+        let err = |text: &str| parse_ply(text.as_bytes(), None).err().unwrap();
+        assert_eq!(err("solid cube\n"), "not a PLY file");
+        assert!(err("ply\nformat ascii 1.0\nelement vertex 1\n").contains("end_header"));
+        assert!(err("ply\nformat ascii 1.0\nproperty float x\nend_header\n").starts_with("line 3"));
+        let truncated = format!("ply\nformat ascii 1.0\n{}0 0 0 1 1 1\n", HEADER);
+        assert_eq!(err(&truncated), "vertex 1: unexpected end of data");
+        let bad_index = format!(
+            "ply\nformat ascii 1.0\n{}{}3 0 1 9\n",
+            HEADER,
+            "0 0 0 0 0 0\n".repeat(4)
+        );
+        assert!(err(&bad_index).contains("indexes past 4 vertices"));
+
+        // A list count near 2^32 is refused, not allocated.
+        let header = "ply\nformat binary_little_endian 1.0\nelement face 1\n\
+            property list uint int vertex_indices\nend_header\n";
+        let mut bytes = header.as_bytes().to_vec();
+        bytes.extend_from_slice(&4_000_000_000u32.to_le_bytes());
+        bytes.extend_from_slice(&[0; 12]);
+        assert_eq!(
+            parse_ply(&bytes, None).err().unwrap(),
+            "face 0: unexpected end of data"
+        );
+    }
+}
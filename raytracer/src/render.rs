@@ -0,0 +1,488 @@
+use std::sync::{mpsc::channel, Arc};
+
+use image::RgbImage;
+use threadpool::ThreadPool;
+
+use crate::{
+    accumulator::Accumulator,
+    aov::Aov,
+    camera::Camera,
+    environment::Environment,
+    framebuffer::Framebuffer,
+    hittable::Hittable,
+    hittable_list::HittableList,
+    light::Light,
+    light_tree::LightTree,
+    materia::ScatterRecord,
+    rtweekend::seed_sample,
+    sampler::{sample_1d, sample_2d, start_bounce, start_sample, SamplerKind},
+    tonemap::ToneMapping,
+    Ray, Vec3,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderSettings {
+    pub width: usize,
+    pub height: usize,
+    pub samples_per_pixel: usize,
+    pub max_depth: i32,
+    // Number of worker threads, and how many row bands the image is split into.
+    pub threads: usize,
+    pub jobs: usize,
+    // Every sample's random numbers derive from the seed, its pixel and its index.
+    pub seed: u64,
+    pub sampler: SamplerKind,
+    // Extra passes rendered alongside the color, see `RenderOutput::aovs`.
+    pub aovs: Vec<Aov>,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            width: 800,
+            height: 800,
+            samples_per_pixel: 2000,
+            max_depth: 50,
+            threads: 16,
+            jobs: 32,
+            seed: 0,
+            sampler: SamplerKind::Sobol,
+            aovs: Vec::new(),
+        }
+    }
+}
+
+pub struct RenderOutput {
+    pub color: Framebuffer,
+    // One framebuffer per entry of `RenderSettings::aovs`, in the same order.
+    pub aovs: Vec<(Aov, Framebuffer)>,
+}
+
+pub struct Renderer {
+    pub settings: RenderSettings,
+    pub world: Arc<dyn Hittable>,
+    // The area lights given to `new`, built into a tree once, in `new`, and shared by every
+    // pass.
+    pub light_tree: Arc<LightTree>,
+    // Point, spot and directional lights, which have no geometry; none by default.
+    pub punctual_lights: Arc<Vec<Arc<dyn Light>>>,
+    pub camera: Camera,
+    pub background: Vec3,
+    // Replaces `background` for rays that leave the scene and is sampled as a light.
+    pub environment: Option<Arc<dyn Environment>>,
+}
+
+impl Renderer {
+    pub fn new(
+        settings: RenderSettings,
+        world: Arc<dyn Hittable>,
+        lights: Arc<HittableList>,
+        camera: Camera,
+        background: Vec3,
+    ) -> Self {
+        Self {
+            settings,
+            world,
+            light_tree: Arc::new(LightTree::new(&lights.objects)),
+            punctual_lights: Arc::new(Vec::new()),
+            camera,
+            background,
+            environment: None,
+        }
+    }
+
+    pub fn render(&self) -> RgbImage {
+        self.render_linear().to_rgb_image(&ToneMapping::default())
+    }
+
+    pub fn render_linear(&self) -> Framebuffer {
+        self.render_linear_with_progress(|_, _| {})
+    }
+
+    pub fn render_linear_with_progress<F: FnMut(usize, usize)>(&self, progress: F) -> Framebuffer {
+        self.render_passes_with_progress(progress).color
+    }
+
+    // `progress` is called with (finished jobs, total jobs) each time a row band is done.
+    pub fn render_passes_with_progress<F: FnMut(usize, usize)>(&self, progress: F) -> RenderOutput {
+        let mut acc = self.accumulator();
+        self.render_pass(&mut acc, self.settings.samples_per_pixel, progress);
+        acc.output()
+    }
+
+    // An empty accumulator matching the image size and passes of these settings.
+    pub fn accumulator(&self) -> Accumulator {
+        Accumulator::new(
+            self.settings.width,
+            self.settings.height,
+            &self.settings.aovs,
+        )
+    }
+
+    // Adds passes of `pass_samples` samples per pixel over the whole image until `acc` holds
+    // `samples_per_pixel` samples, calling `after_pass` once each pass is accumulated.
+    pub fn render_progressive<F: FnMut(&Accumulator)>(
+        &self,
+        acc: &mut Accumulator,
+        pass_samples: usize,
+        mut after_pass: F,
+    ) {
+        while acc.samples < self.settings.samples_per_pixel {
+            let samples = pass_samples
+                .max(1)
+                .min(self.settings.samples_per_pixel - acc.samples);
+            self.render_pass(acc, samples, |_, _| {});
+            after_pass(acc);
+        }
+    }
+
+    // Renders `samples` more samples per pixel and adds them to `acc`.
+    pub fn render_pass<F: FnMut(usize, usize)>(
+        &self,
+        acc: &mut Accumulator,
+        samples: usize,
+        mut progress: F,
+    ) {
+        let RenderSettings {
+            width: image_width,
+            height: image_height,
+            max_depth,
+            threads: n_workers,
+            jobs,
+            seed,
+            ..
+        } = self.settings;
+        assert!(
+            image_width >= 2 && image_height >= 2,
+            "image must be at least 2x2 pixels"
+        );
+        assert!(
+            acc.fits(image_width, image_height, &self.settings.aovs),
+            "accumulator does not match the render settings"
+        );
+        let first_pass = acc.samples == 0;
+        let first_sample = acc.samples;
+        let sampler = self
+            .settings
+            .sampler
+            .build(seed, self.settings.samples_per_pixel);
+        let n_jobs = jobs.max(1).min(image_height);
+        let (tx, rx) = channel();
+        let pool = ThreadPool::new(n_workers.max(1));
+
+        for i in 0..n_jobs {
+            let tx = tx.clone();
+            let world = self.world.clone();
+            let lights = self.light_tree.clone();
+            let punctual_lights = self.punctual_lights.clone();
+            let cam = self.camera;
+            let background = self.background;
+            let environment = self.environment.clone();
+            let aovs = self.settings.aovs.clone();
+            let sampler = sampler.clone();
+            pool.execute(move || {
+                let row_begin = image_height * i / n_jobs;
+                let row_end = image_height * (i + 1) / n_jobs;
+                let render_height = row_end - row_begin;
+                // Bands hold sums, the accumulator divides by the sample count.
+                let mut band = Framebuffer::new(image_width, render_height);
+                let mut aov_bands = vec![Framebuffer::new(image_width, render_height); aovs.len()];
+                for x in 0..image_width {
+                    for (band_y, y) in (row_begin..row_end).enumerate() {
+                        let mut pixel_color = Vec3::new(0.0, 0.0, 0.0);
+                        let mut aov_sums = vec![Vec3::zero(); aovs.len()];
+                        for s in 0..samples {
+                            let pixel = (y * image_width + x) as u64;
+                            let index = (first_sample + s) as u64;
+                            seed_sample(seed, pixel, index);
+                            start_sample(&sampler, pixel, index);
+                            let (jx, jy) = sample_2d();
+                            let u = (x as f64 + jx) / ((image_width - 1) as f64);
+                            let v = ((image_height - y) as f64 - jy) / ((image_height - 1) as f64);
+                            let r = cam.get_ray(u, v);
+                            let c = color(
+                                &r,
+                                &background,
+                                &*world,
+                                &lights,
+                                &punctual_lights,
+                                environment.as_deref(),
+                                max_depth,
+                            );
+                            // A NaN sample would poison the whole pixel, drop it instead.
+                            if !(c.x.is_nan() || c.y.is_nan() || c.z.is_nan()) {
+                                pixel_color += c;
+                            }
+                            // After the path, so asking for AOVs doesn't change the image.
+                            for (aov, sum) in aovs.iter().zip(aov_sums.iter_mut()) {
+                                if aov.averaged() || (first_pass && s == 0) {
+                                    *sum += aov.sample(&r, &*world);
+                                }
+                            }
+                        }
+                        band.set(x, band_y, pixel_color);
+                        for (sum, aov_band) in aov_sums.into_iter().zip(aov_bands.iter_mut()) {
+                            aov_band.set(x, band_y, sum);
+                        }
+                    }
+                }
+                tx.send((row_begin, band, aov_bands))
+                    .expect("failed to send result");
+            });
+        }
+
+        for (done, (row_begin, band, aov_bands)) in rx.iter().take(n_jobs).enumerate() {
+            for y in 0..band.height {
+                for x in 0..image_width {
+                    let sum = acc.color.get(x, row_begin + y) + band.get(x, y);
+                    acc.color.set(x, row_begin + y, sum);
+                    for ((_, aov_acc), aov_band) in acc.aovs.iter_mut().zip(aov_bands.iter()) {
+                        let sum = aov_acc.get(x, row_begin + y) + aov_band.get(x, y);
+                        aov_acc.set(x, row_begin + y, sum);
+                    }
+                }
+            }
+            progress(done + 1, n_jobs);
+        }
+        acc.samples += samples;
+    }
+}
+
+pub fn color(
+    r: &Ray,
+    background: &Vec3,
+    world: &dyn Hittable,
+    lights: &LightTree,
+    punctual_lights: &[Arc<dyn Light>],
+    environment: Option<&dyn Environment>,
+    depth: i32,
+) -> Vec3 {
+    let lights = Lights {
+        area: lights,
+        punctual: punctual_lights,
+        environment,
+    };
+    trace(r, background, world, &lights, depth, None)
+}
+
+struct Lights<'a> {
+    area: &'a LightTree,
+    punctual: &'a [Arc<dyn Light>],
+    environment: Option<&'a dyn Environment>,
+}
+
+// The shadow ray aims at the area lights or the environment, split by how much light
+// each sends to the shading point. Which area light is up to the `LightTree`.
+impl<'a> Lights<'a> {
+    fn can_sample(&self) -> bool {
+        !self.area.is_empty() || self.environment.is_some()
+    }
+
+    // A surface sees half the environment's sphere. Like `Sky` does for the sun, both
+    // sides keep some of the samples.
+    fn environment_probability(&self, o: Vec3) -> f64 {
+        match self.environment {
+            None => 0.0,
+            Some(_) if self.area.is_empty() => 1.0,
+            Some(env) => {
+                let e = 0.5 * env.power();
+                let a = self.area.incident_power(o);
+                if e + a > 0.0 {
+                    (e / (e + a)).clamp(0.1, 0.9)
+                } else {
+                    0.5
+                }
+            }
+        }
+    }
+
+    fn random(&self, o: Vec3) -> Vec3 {
+        match self.environment {
+            Some(env) if sample_1d() < self.environment_probability(o) => env.random(),
+            _ => self.area.random(o),
+        }
+    }
+
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
+        let p = self.environment_probability(*o);
+        let mut pdf = 0.0;
+        if let Some(env) = self.environment {
+            pdf += p * env.pdf_value(v);
+        }
+        if p < 1.0 {
+            pdf += (1.0 - p) * self.area.pdf_value(o, v);
+        }
+        pdf
+    }
+}
+
+// At every diffuse vertex the light is estimated twice: by a shadow ray towards a point
+// sampled on the area lights (or a direction of the environment map) and by the ray the
+// material samples. Each estimate is weighted with
+// the power heuristic against the pdf the other strategy has for the same direction, so
+// together they count every light once. Punctual lights can only be reached by the
+// shadow ray and take no weight. `bsdf_sample` is the origin and pdf of the
+// material-sampled ray that `r` is; emission found by camera rays and specular bounces
+// could not have been light sampled and counts in full.
+fn trace(
+    r: &Ray,
+    background: &Vec3,
+    world: &dyn Hittable,
+    lights: &Lights,
+    depth: i32,
+    bsdf_sample: Option<(Vec3, f64)>,
+) -> Vec3 {
+    if depth <= 0 {
+        return Vec3::zero();
+    }
+    let (rec, mut emitted) = match world.hit(r, 0.0001, f64::INFINITY) {
+        Some(rec) => {
+            let emitted = rec.mat_ptr.emitted(r, &rec, rec.u, rec.v, &rec.p);
+            (Some(rec), emitted)
+        }
+        None => match lights.environment {
+            Some(env) => (None, env.radiance(&r.dir)),
+            None => return *background,
+        },
+    };
+    if let Some((origin, bsdf_pdf)) = bsdf_sample {
+        if lights.can_sample() && emitted.len_squared() > 0.0 {
+            let light_pdf = lights.pdf_value(&origin, &r.dir);
+            emitted = emitted * power_heuristic(bsdf_pdf, light_pdf);
+        }
+    }
+    let rec = match rec {
+        Some(rec) => rec,
+        None => return emitted,
+    };
+    start_bounce();
+    let mut scattered = Ray::new(rec.p, Vec3::zero(), r.time);
+    let mut attenuation = Vec3::zero();
+    let mut srec = ScatterRecord::new();
+    if !rec
+        .mat_ptr
+        .scatter(r, &rec, &mut attenuation, &mut scattered, &mut srec)
+    {
+        return emitted;
+    }
+    if srec.is_specular {
+        let t = trace(
+            &srec.specular_ray,
+            background,
+            world,
+            lights,
+            depth - 1,
+            None,
+        );
+        return emitted + Vec3::elementmul(srec.attenuation, t);
+    }
+
+    // Next-event estimation. Whatever the shadow ray meets first is what the light sample
+    // sees, so an occluder simply contributes its own (usually zero) emission.
+    let mut direct = Vec3::zero();
+    if lights.can_sample() {
+        let mut shadow = Ray::new(rec.p, lights.random(rec.p), r.time);
+        let light_pdf = lights.pdf_value(&rec.p, &shadow.dir);
+        let f = rec.mat_ptr.scattering_pdf(r, &rec, &mut shadow);
+        if light_pdf > 0.0 && f > 0.0 {
+            let le = match world.hit(&shadow, 0.0001, f64::INFINITY) {
+                Some(hit) => hit.mat_ptr.emitted(&shadow, &hit, hit.u, hit.v, &hit.p),
+                None => lights
+                    .environment
+                    .map_or(Vec3::zero(), |env| env.radiance(&shadow.dir)),
+            };
+            let weight = power_heuristic(light_pdf, srec.pdf_ptr.value(&shadow.dir));
+            direct = Vec3::elementmul(srec.attenuation, le) * (f * weight / light_pdf);
+        }
+    }
+    for light in lights.punctual.iter() {
+        let sample = match light.sample(rec.p) {
+            Some(sample) => sample,
+            None => continue,
+        };
+        let mut shadow = Ray::new(rec.p, sample.dir, r.time);
+        let f = rec.mat_ptr.scattering_pdf(r, &rec, &mut shadow);
+        if f > 0.0 && world.hit(&shadow, 0.0001, sample.distance).is_none() {
+            direct += Vec3::elementmul(srec.attenuation, sample.irradiance) * f;
+        }
+    }
+
+    scattered.dir = srec.pdf_ptr.generate();
+    let bsdf_pdf = srec.pdf_ptr.value(&scattered.dir);
+    let f = rec.mat_ptr.scattering_pdf(r, &rec, &mut scattered);
+    if bsdf_pdf <= 0.0 || f <= 0.0 {
+        return emitted + direct;
+    }
+    let t = trace(
+        &scattered,
+        background,
+        world,
+        lights,
+        depth - 1,
+        Some((rec.p, bsdf_pdf)),
+    );
+    emitted + direct + Vec3::elementmul(srec.attenuation, t) * (f / bsdf_pdf)
+}
+
+// Veach's power heuristic with an exponent of 2, for one sample from each strategy.
+pub fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        aarec::XzRect, environment::EnvironmentMap, materia::DiffuseLight, texture::SolidColor,
+    };
+    use std::f64::consts::PI;
+
+    #[test]
+    fn test_environment_split() {
+        let mat = Arc::new(DiffuseLight::new(Arc::new(SolidColor::new(
+            Vec3::ones() * 4.0,
+        ))));
+        let lamp: Arc<dyn Hittable> = Arc::new(XzRect::new(-1.0, 1.0, -1.0, 1.0, 2.0, mat));
+        let tree = LightTree::new(&[lamp]);
+        let sky =
+            |brightness: f64| EnvironmentMap::new(1, 1, vec![Vec3::ones() * brightness], 0.0, 1.0);
+        let o = Vec3::zero();
+
+        // A dim sky and a bright one get the floor and the ceiling.
+        let (dim, bright) = (sky(1e-4), sky(1e4));
+        let lights = |env| Lights {
+            area: &tree,
+            punctual: &[],
+            environment: Some(env),
+        };
+        assert_eq!(lights(&dim).environment_probability(o), 0.1);
+        assert_eq!(lights(&bright).environment_probability(o), 0.9);
+
+        // Close to even, the mixture's pdf still integrates to one over the sphere.
+        let even = sky(1.0);
+        let lights = lights(&even);
+        let p = lights.environment_probability(o);
+        assert!(p > 0.1 && p < 0.9, "{}", p);
+        let n = 400;
+        let mut sum = 0.0;
+        for i in 0..n {
+            for j in 0..2 * n {
+                let theta = (i as f64 + 0.5) / n as f64 * PI;
+                let phi = (j as f64 + 0.5) / (2 * n) as f64 * 2.0 * PI;
+                let d = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                let d_omega = theta.sin() * (PI / n as f64) * (PI / n as f64);
+                sum += lights.pdf_value(&o, &d) * d_omega;
+            }
+        }
+        assert!((sum - 1.0).abs() < 0.02, "{}", sum);
+    }
+}
@@ -0,0 +1,426 @@
+use std::{cell::RefCell, sync::Arc};
+
+use crate::rtweekend::{random_double2, splitmix64};
+
+// Sample sources for the renderer. A sampler is a pure function of (pixel, sample index,
+// dimension), so the threads can share one and every sample is reproducible.
+pub trait Sampler: Send + Sync {
+    // Coordinate `dim` of sample `index` in `pixel`, uniform in [0, 1).
+    fn get_1d(&self, pixel: u64, index: u64, dim: u32) -> f64;
+
+    // Coordinates `dim` and `dim + 1`; samplers that stratify in 2D override this.
+    fn get_2d(&self, pixel: u64, index: u64, dim: u32) -> (f64, f64) {
+        (
+            self.get_1d(pixel, index, dim),
+            self.get_1d(pixel, index, dim + 1),
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl SamplerKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "independent" => Some(SamplerKind::Independent),
+            "stratified" => Some(SamplerKind::Stratified),
+            "halton" => Some(SamplerKind::Halton),
+            "sobol" => Some(SamplerKind::Sobol),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SamplerKind::Independent => "independent",
+            SamplerKind::Stratified => "stratified",
+            SamplerKind::Halton => "halton",
+            SamplerKind::Sobol => "sobol",
+        }
+    }
+
+    // `samples` is the number of samples per pixel the render will take in total.
+    pub fn build(&self, seed: u64, samples: usize) -> Arc<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Arc::new(IndependentSampler),
+            SamplerKind::Stratified => Arc::new(StratifiedSampler::new(seed, samples)),
+            SamplerKind::Halton => Arc::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Arc::new(SobolSampler::new(seed)),
+        }
+    }
+
+    // The total `build` lays the samples out for, or 0 when where a sample falls doesn't
+    // depend on it. Only the strata of the stratified sampler do.
+    pub fn layout_samples(&self, samples: usize) -> usize {
+        match self {
+            SamplerKind::Stratified => samples,
+            _ => 0,
+        }
+    }
+}
+
+// Plain uniform random numbers from the per-sample PCG stream.
+pub struct IndependentSampler;
+
+impl Sampler for IndependentSampler {
+    fn get_1d(&self, _pixel: u64, _index: u64, _dim: u32) -> f64 {
+        random_double2(0.0, 1.0)
+    }
+}
+
+// Jittered strata, shuffled independently per pixel and dimension. 1D requests split
+// [0, 1) into `samples` strata, 2D requests use a square grid of at least `samples` cells.
+pub struct StratifiedSampler {
+    seed: u64,
+    samples: u32,
+}
+
+impl StratifiedSampler {
+    pub fn new(seed: u64, samples: usize) -> Self {
+        Self {
+            seed,
+            samples: samples.max(1) as u32,
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn get_1d(&self, pixel: u64, index: u64, dim: u32) -> f64 {
+        let h = hash(&[self.seed, pixel, dim as u64]);
+        let n = self.samples;
+        let stratum = permute(index as u32 % n, n, h as u32);
+        (stratum as f64 + to_unit(hash(&[h, index]))) / n as f64
+    }
+
+    fn get_2d(&self, pixel: u64, index: u64, dim: u32) -> (f64, f64) {
+        let h = hash(&[self.seed, pixel, dim as u64]);
+        let m = (self.samples as f64).sqrt().ceil() as u32;
+        let stratum = permute(index as u32 % (m * m), m * m, h as u32);
+        let jitter = hash(&[h, index]);
+        (
+            ((stratum % m) as f64 + to_unit(jitter)) / m as f64,
+            ((stratum / m) as f64 + to_unit(splitmix64(jitter))) / m as f64,
+        )
+    }
+}
+
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+// Radical inverses in prime bases, Owen-scrambled per pixel and dimension; without the
+// scrambling the higher bases are strongly correlated at low sample counts. Past the
+// prime table the dimensions fall back to independent samples.
+pub struct HaltonSampler {
+    seed: u64,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn get_1d(&self, pixel: u64, index: u64, dim: u32) -> f64 {
+        let base = match PRIMES.get(dim as usize) {
+            Some(base) => *base,
+            None => return random_double2(0.0, 1.0),
+        };
+        let h = hash(&[self.seed, pixel, dim as u64]);
+        owen_scrambled_radical_inverse(base, index, h)
+    }
+}
+
+// Each digit is permuted by a hash of the digits before it. Enough digits are produced
+// for 32 bits of precision, past the last non-zero digit of `index` too.
+fn owen_scrambled_radical_inverse(base: u64, mut index: u64, seed: u64) -> f64 {
+    let digits = (32.0 / (base as f64).log2()).ceil() as u32;
+    let inv_base = 1.0 / base as f64;
+    let mut inv = inv_base;
+    let mut x = 0.0;
+    let mut h = seed;
+    for _ in 0..digits {
+        let digit = index % base;
+        x += permute(digit as u32, base as u32, h as u32) as f64 * inv;
+        h = splitmix64(h ^ digit);
+        index /= base;
+        inv *= inv_base;
+    }
+    x.min(1.0 - f64::EPSILON)
+}
+
+// Owen-scrambled Sobol points with hash-based scrambling (Burley 2020). Only the first
+// two Sobol dimensions are used, so every pair of dimensions is a (0, 2)-sequence; the
+// pairs are decorrelated by giving each its own shuffle and scramble.
+pub struct SobolSampler {
+    seed: u64,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn get_1d(&self, pixel: u64, index: u64, dim: u32) -> f64 {
+        let pair = hash(&[self.seed, pixel, (dim / 2) as u64]) as u32;
+        let shuffled = nested_uniform_scramble(index as u32, pair);
+        let x = sobol(shuffled, dim % 2);
+        let x = nested_uniform_scramble(x, splitmix64(pair as u64 ^ dim as u64) as u32);
+        x as f64 / 4_294_967_296.0
+    }
+}
+
+// Direction numbers of the second Sobol dimension (primitive polynomial x + 1) as 32-bit
+// fractions; the first dimension is the van der Corput sequence.
+const SOBOL_MATRIX: [u32; 32] = sobol_matrix();
+
+const fn sobol_matrix() -> [u32; 32] {
+    let mut v = [0u32; 32];
+    v[0] = 1 << 31;
+    let mut i = 1;
+    while i < 32 {
+        v[i] = v[i - 1] ^ (v[i - 1] >> 1);
+        i += 1;
+    }
+    v
+}
+
+fn sobol(index: u32, dim: u32) -> u32 {
+    if dim == 0 {
+        return index.reverse_bits();
+    }
+    let mut x = 0;
+    let mut index = index;
+    let mut bit = 0;
+    while index != 0 {
+        if index & 1 == 1 {
+            x ^= SOBOL_MATRIX[bit];
+        }
+        index >>= 1;
+        bit += 1;
+    }
+    x
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+// Kensler's hashed permutation of [0, l) ("Correlated Multi-Jittered Sampling").
+fn permute(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    i.wrapping_add(p) % l
+}
+
+fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0, |h, v| splitmix64(h ^ v))
+}
+
+fn to_unit(h: u64) -> f64 {
+    (h >> 11) as f64 / 9_007_199_254_740_992.0
+}
+
+struct SampleState {
+    sampler: Arc<dyn Sampler>,
+    pixel: u64,
+    index: u64,
+    dim: u32,
+    // Where the current block of dimensions ends.
+    end: u32,
+    bounce: u32,
+}
+
+// The camera uses dimensions 0..CAMERA_DIMENSIONS, then every bounce starts on its own block
+// of BOUNCE_DIMENSIONS, so the same dimension means the same thing in every sample even
+// when paths take different branches. A block holds a bounce's light choice, light point,
+// material choice and scattered direction; draws past its end come from the per-sample
+// random stream, never from the next block, which the next bounce would then repeat.
+const CAMERA_DIMENSIONS: u32 = 6;
+const BOUNCE_DIMENSIONS: u32 = 12;
+
+thread_local! {
+    static STATE: RefCell<Option<SampleState>> = RefCell::new(None);
+}
+
+// Makes `sample_1d`/`sample_2d` on this thread walk the dimensions of one camera sample.
+pub fn start_sample(sampler: &Arc<dyn Sampler>, pixel: u64, index: u64) {
+    STATE.with(|state| {
+        *state.borrow_mut() = Some(SampleState {
+            sampler: sampler.clone(),
+            pixel,
+            index,
+            dim: 0,
+            end: CAMERA_DIMENSIONS,
+            bounce: 0,
+        })
+    });
+}
+
+// Called by the integrator at every scattering event.
+pub fn start_bounce() {
+    STATE.with(|state| {
+        if let Some(s) = state.borrow_mut().as_mut() {
+            s.dim = CAMERA_DIMENSIONS + s.bounce * BOUNCE_DIMENSIONS;
+            s.end = s.dim + BOUNCE_DIMENSIONS;
+            s.bounce += 1;
+        }
+    });
+}
+
+// Outside of a camera sample (e.g. while building a scene) these fall back to the
+// thread's random number generator.
+pub fn sample_1d() -> f64 {
+    STATE.with(|state| match state.borrow_mut().as_mut() {
+        Some(s) if s.dim < s.end => {
+            s.dim += 1;
+            s.sampler.get_1d(s.pixel, s.index, s.dim - 1)
+        }
+        _ => random_double2(0.0, 1.0),
+    })
+}
+
+pub fn sample_2d() -> (f64, f64) {
+    STATE.with(|state| {
+        if let Some(s) = state.borrow_mut().as_mut() {
+            // Keep pairs on even dimensions so they line up with the samplers' 2D strata.
+            let dim = s.dim + s.dim % 2;
+            if dim + 2 <= s.end {
+                s.dim = dim + 2;
+                return s.sampler.get_2d(s.pixel, s.index, dim);
+            }
+            s.dim = s.end;
+        }
+        (random_double2(0.0, 1.0), random_double2(0.0, 1.0))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Both put exactly one of 16 points in each cell of a 4x4 grid.
+    #[test]
+    fn test_2d_stratification() {
+        for kind in [SamplerKind::Stratified, SamplerKind::Sobol].iter() {
+            let sampler = kind.build(3, 16);
+            for dim in [0, 2, 8].iter() {
+                let mut cells = [0; 16];
+                for i in 0..16 {
+                    let (x, y) = sampler.get_2d(42, i, *dim);
+                    cells[(y * 4.0) as usize * 4 + (x * 4.0) as usize] += 1;
+                }
+                assert_eq!(cells, [1; 16], "{:?} dim {}", kind, dim);
+            }
+        }
+    }
+
+    #[test]
+    fn test_1d_stratification() {
+        for kind in [
+            SamplerKind::Stratified,
+            SamplerKind::Halton,
+            SamplerKind::Sobol,
+        ]
+        .iter()
+        {
+            let sampler = kind.build(5, 8);
+            let mut cells = [0; 8];
+            for i in 0..8 {
+                let x = sampler.get_1d(7, i, 0);
+                assert!((0.0..1.0).contains(&x));
+                cells[(x * 8.0) as usize] += 1;
+            }
+            assert_eq!(cells, [1; 8], "{:?}", kind);
+        }
+    }
+
+    // Returns the dimension itself, to see which ones are drawn.
+    struct DimensionSampler;
+
+    impl Sampler for DimensionSampler {
+        fn get_1d(&self, _pixel: u64, _index: u64, dim: u32) -> f64 {
+            dim as f64
+        }
+    }
+
+    #[test]
+    fn test_bounces_keep_to_their_blocks() {
+        let sampler: Arc<dyn Sampler> = Arc::new(DimensionSampler);
+        start_sample(&sampler, 0, 0);
+        let mut bounces = Vec::new();
+        for _ in 0..3 {
+            start_bounce();
+            // More than a block holds, in 1D and 2D draws.
+            let mut dims = Vec::new();
+            for _ in 0..6 {
+                dims.push(sample_1d());
+                let (x, y) = sample_2d();
+                dims.push(x);
+                dims.push(y);
+            }
+            bounces.push(dims);
+        }
+        STATE.with(|state| *state.borrow_mut() = None);
+        for (b, dims) in bounces.iter().enumerate() {
+            let begin = (CAMERA_DIMENSIONS + b as u32 * BOUNCE_DIMENSIONS) as f64;
+            let from_sampler: Vec<f64> = dims.iter().copied().filter(|d| *d >= 1.0).collect();
+            assert!(!from_sampler.is_empty());
+            for d in from_sampler {
+                assert!(d >= begin && d < begin + BOUNCE_DIMENSIONS as f64, "{}", d);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sobol_matrices() {
+        let points: Vec<u32> = (0..4).map(|i| sobol(i, 1) >> 30).collect();
+        assert_eq!(points, [0b00, 0b10, 0b11, 0b01]);
+    }
+}
@@ -0,0 +1,1306 @@
+use std::{collections::HashMap, fs, path::Path, sync::Arc};
+
+use serde::Deserialize;
+
+use crate::{
+    aarec::{RotateY, Translate, XyRect, XzRect, YzRect},
+    box_::Box_,
+    bvh::{Bvh, BvhOptions, BvhStats},
+    cache::MeshCache,
+    camera::Camera,
+    constant_medium::{ConstantMedium, Isotropic},
+    environment::{Environment, EnvironmentMap},
+    gltf::load_gltf,
+    hittable::{Hittable, ObjectId},
+    hittable_list::HittableList,
+    instance::Instance,
+    light::{DirectionalLight, Light, PointLight, SpotLight},
+    materia::{Dielectric, DiffuseLight, Lambertian, Material, Metal},
+    matrix::Mat4,
+    mesh::{MeshData, TriangleMesh},
+    obj::load_obj_cached,
+    onb::FlipFace,
+    ply::load_ply_cached,
+    rtweekend::{random_double, random_double2},
+    sky::Sky,
+    sphere::{MovingSphere, Sphere},
+    stl::load_stl_cached,
+    texture::{CheckerTexture, ImageTexture, NoiseTexture, SolidColor, Texture},
+    transform::Transform,
+    Vec3,
+};
+
+// A scene file is JSON, or YAML when its name ends in .yaml or .yml: a camera, a background
+// colour, named textures and materials and the objects of the world. Every object with an
+// emissive material is sampled as a light; a "lights" list, when given, replaces that with
+// objects of its own. Point, spot and directional lights go in "punctual_lights". An
+// "environment" image or a daylight "sky", when given, lights the scene from all around in
+// place of the background colour.
+//
+// {
+//     "camera": { "lookfrom": [278, 278, -800], "lookat": [278, 278, 0], "vfov": 40 },
+//     "background": [0, 0, 0],
+//     "materials": { "light": { "type": "diffuse_light", "emit": [15, 15, 15] } },
+//     "objects": [ { "type": "xz_rect", "x0": 213, "x1": 343, "z0": 227, "z1": 332, "k": 554, "material": "light" } ]
+// }
+
+pub struct Scene {
+    pub world: Bvh,
+    pub lights: HittableList,
+    pub punctual_lights: Vec<Arc<dyn Light>>,
+    pub camera: CameraDesc,
+    pub background: Vec3,
+    pub environment: Option<Arc<dyn Environment>>,
+    // Build statistics of every mesh and object group, labelled by file or kind.
+    pub bvh_stats: Vec<(String, BvhStats)>,
+    // Problems that did not stop the scene from loading, such as an unusable mesh cache.
+    pub warnings: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneFile {
+    // May be left out when a glTF object provides the camera.
+    pub camera: Option<CameraDesc>,
+    #[serde(default)]
+    pub background: [f64; 3],
+    pub environment: Option<EnvironmentDesc>,
+    pub sky: Option<SkyDesc>,
+    #[serde(default)]
+    pub textures: HashMap<String, TextureDesc>,
+    #[serde(default)]
+    pub materials: HashMap<String, MaterialDesc>,
+    // Objects built once and placed any number of times by `instance` objects.
+    #[serde(default)]
+    pub assets: HashMap<String, ObjectDesc>,
+    #[serde(default)]
+    pub objects: Vec<ObjectDesc>,
+    #[serde(default)]
+    pub lights: Vec<ObjectDesc>,
+    #[serde(default)]
+    pub punctual_lights: Vec<LightDesc>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CameraDesc {
+    pub lookfrom: [f64; 3],
+    pub lookat: [f64; 3],
+    #[serde(default = "default_vup")]
+    pub vup: [f64; 3],
+    #[serde(default = "default_vfov")]
+    pub vfov: f64,
+    #[serde(default = "default_aspect_ratio")]
+    pub aspect_ratio: f64,
+    #[serde(default)]
+    pub aperture: f64,
+    #[serde(default = "default_focus_dist")]
+    pub focus_dist: f64,
+    #[serde(default)]
+    pub time0: f64,
+    #[serde(default = "default_time1")]
+    pub time1: f64,
+}
+
+fn default_vup() -> [f64; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+fn default_vfov() -> f64 {
+    20.0
+}
+
+fn default_aspect_ratio() -> f64 {
+    1.0
+}
+
+fn default_focus_dist() -> f64 {
+    10.0
+}
+
+fn default_time1() -> f64 {
+    1.0
+}
+
+impl CameraDesc {
+    pub fn build(&self, aspect_ratio: f64) -> Camera {
+        Camera::new(
+            vec3(self.lookfrom),
+            vec3(self.lookat),
+            vec3(self.vup),
+            self.vfov,
+            aspect_ratio,
+            self.aperture,
+            self.focus_dist,
+            self.time0,
+            self.time1,
+        )
+    }
+}
+
+// A colour can be given inline as [r, g, b] or as the name of a texture.
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum ColorOrTexture {
+    Color([f64; 3]),
+    Texture(String),
+}
+
+// Places a model: scaled, then rotated around x, y and z, then by `angle` around `axis`
+// (all in degrees), then translated. `scale` is either a single factor or one per axis.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PlacementDesc {
+    #[serde(default)]
+    pub translate: [f64; 3],
+    #[serde(default)]
+    pub rotate: [f64; 3],
+    pub axis: Option<[f64; 3]>,
+    #[serde(default)]
+    pub angle: f64,
+    #[serde(default = "default_placement_scale")]
+    pub scale: ScaleDesc,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum ScaleDesc {
+    Uniform(f64),
+    Axes([f64; 3]),
+}
+
+fn default_placement_scale() -> ScaleDesc {
+    ScaleDesc::Uniform(1.0)
+}
+
+fn placement(desc: &Option<PlacementDesc>) -> Mat4 {
+    desc.as_ref().map_or_else(Mat4::identity, |t| t.matrix())
+}
+
+impl PlacementDesc {
+    pub fn matrix(&self) -> Mat4 {
+        let scale = match self.scale {
+            ScaleDesc::Uniform(s) => Vec3::new(s, s, s),
+            ScaleDesc::Axes(s) => vec3(s),
+        };
+        let axis = self
+            .axis
+            .map_or_else(Mat4::identity, |axis| Mat4::rotate(vec3(axis), self.angle));
+        Mat4::translate(vec3(self.translate))
+            * axis
+            * Mat4::rotate_z(self.rotate[2])
+            * Mat4::rotate_y(self.rotate[1])
+            * Mat4::rotate_x(self.rotate[0])
+            * Mat4::scale(scale)
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TextureDesc {
+    Solid { color: [f64; 3] },
+    Checker { even: [f64; 3], odd: [f64; 3] },
+    Noise { scale: f64 },
+    Image { file: String },
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum MaterialDesc {
+    Lambertian { albedo: ColorOrTexture },
+    Metal { albedo: [f64; 3], fuzz: f64 },
+    Dielectric { ir: f64 },
+    DiffuseLight { emit: ColorOrTexture },
+    Isotropic { albedo: ColorOrTexture },
+}
+
+// An equirectangular image around the scene, turned `rotation` degrees about the y axis
+// and scaled by `intensity`.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct EnvironmentDesc {
+    pub file: String,
+    #[serde(default)]
+    pub rotation: f64,
+    #[serde(default = "default_intensity")]
+    pub intensity: f64,
+}
+
+fn default_intensity() -> f64 {
+    1.0
+}
+
+// A clear sky and the sun, see `Sky`. The sun's `elevation` above the horizon and its
+// `azimuth` from -z towards +x are in degrees.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SkyDesc {
+    #[serde(default = "default_turbidity")]
+    pub turbidity: f64,
+    pub elevation: f64,
+    #[serde(default)]
+    pub azimuth: f64,
+    #[serde(default = "default_sky_intensity")]
+    pub intensity: f64,
+}
+
+fn default_turbidity() -> f64 {
+    3.0
+}
+
+// Puts the sky near the horizon at about the [0.7, 0.8, 1.0] background of the
+// outdoor scenes.
+fn default_sky_intensity() -> f64 {
+    0.1
+}
+
+impl SkyDesc {
+    pub fn build(&self) -> Result<Sky, String> {
+        // The range the model was fitted over.
+        if !(1.7..=10.0).contains(&self.turbidity) {
+            return Err(format!(
+                "sky: turbidity {} is outside 1.7 to 10",
+                self.turbidity
+            ));
+        }
+        if !(0.0..=90.0).contains(&self.elevation) {
+            return Err(format!(
+                "sky: sun elevation {} is outside 0 to 90 degrees",
+                self.elevation
+            ));
+        }
+        Ok(Sky::new(
+            self.turbidity,
+            self.elevation,
+            self.azimuth,
+            self.intensity,
+        ))
+    }
+}
+
+// Lights with no surface. Intensities are in watts per steradian, the sun's irradiance in
+// watts per square metre; angles are in degrees.
+#[derive(Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum LightDesc {
+    Point {
+        position: [f64; 3],
+        intensity: [f64; 3],
+    },
+    // Lights a cone around the line to `target`, fading out over the last `falloff`
+    // degrees of its half-angle `cone_angle`.
+    Spot {
+        position: [f64; 3],
+        target: [f64; 3],
+        intensity: [f64; 3],
+        cone_angle: f64,
+        #[serde(default)]
+        falloff: f64,
+    },
+    // `direction` is the way the light travels.
+    Directional {
+        direction: [f64; 3],
+        irradiance: [f64; 3],
+    },
+}
+
+impl LightDesc {
+    pub fn build(&self) -> Result<Arc<dyn Light>, String> {
+        Ok(match self {
+            LightDesc::Point {
+                position,
+                intensity,
+            } => Arc::new(PointLight::new(vec3(*position), vec3(*intensity))),
+            LightDesc::Spot {
+                position,
+                target,
+                intensity,
+                cone_angle,
+                falloff,
+            } => {
+                let direction = vec3(*target) - vec3(*position);
+                if direction.len_squared() <= 0.0 {
+                    return Err("spot light aimed at its own position".to_string());
+                }
+                Arc::new(SpotLight::new(
+                    vec3(*position),
+                    direction,
+                    vec3(*intensity),
+                    *cone_angle,
+                    *falloff,
+                ))
+            }
+            LightDesc::Directional {
+                direction,
+                irradiance,
+            } => {
+                if vec3(*direction).len_squared() <= 0.0 {
+                    return Err("directional light has no direction".to_string());
+                }
+                Arc::new(DirectionalLight::new(vec3(*direction), vec3(*irradiance)))
+            }
+        })
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ObjectDesc {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+        material: Option<String>,
+    },
+    MovingSphere {
+        center0: [f64; 3],
+        center1: [f64; 3],
+        time0: f64,
+        time1: f64,
+        radius: f64,
+        material: Option<String>,
+    },
+    XyRect {
+        x0: f64,
+        x1: f64,
+        y0: f64,
+        y1: f64,
+        k: f64,
+        material: Option<String>,
+    },
+    XzRect {
+        x0: f64,
+        x1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: Option<String>,
+    },
+    YzRect {
+        y0: f64,
+        y1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: Option<String>,
+    },
+    Box {
+        p0: [f64; 3],
+        p1: [f64; 3],
+        material: Option<String>,
+    },
+    Triangle {
+        a: [f64; 3],
+        b: [f64; 3],
+        c: [f64; 3],
+        material: Option<String>,
+    },
+    // Without a material the faces use the materials of the OBJ's MTL library.
+    Obj {
+        file: String,
+        #[serde(default = "default_scale")]
+        scale: f64,
+        transform: Option<PlacementDesc>,
+        material: Option<String>,
+    },
+    // With `camera` set, the scene is seen through the file's first camera instead.
+    Gltf {
+        file: String,
+        transform: Option<PlacementDesc>,
+        material: Option<String>,
+        #[serde(default)]
+        camera: bool,
+    },
+    Ply {
+        file: String,
+        transform: Option<PlacementDesc>,
+        material: Option<String>,
+    },
+    Stl {
+        file: String,
+        transform: Option<PlacementDesc>,
+        material: Option<String>,
+    },
+    ConstantMedium {
+        boundary: Box<ObjectDesc>,
+        density: f64,
+        albedo: ColorOrTexture,
+    },
+    Translate {
+        offset: [f64; 3],
+        object: Box<ObjectDesc>,
+    },
+    RotateY {
+        angle: f64,
+        object: Box<ObjectDesc>,
+    },
+    Transform {
+        transform: PlacementDesc,
+        object: Box<ObjectDesc>,
+    },
+    FlipFace {
+        object: Box<ObjectDesc>,
+    },
+    // Places the named asset; with a material, it replaces the asset's own.
+    Instance {
+        asset: String,
+        transform: Option<PlacementDesc>,
+        material: Option<String>,
+    },
+    Bvh {
+        objects: Vec<ObjectDesc>,
+        max_leaf_size: Option<usize>,
+    },
+    // Procedural scenes that are generated with random numbers rather than listed by hand.
+    Preset {
+        name: String,
+    },
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+pub fn vec3(a: [f64; 3]) -> Vec3 {
+    Vec3::new(a[0], a[1], a[2])
+}
+
+// Meshes are cached under `mesh_cache` when it is given; see `MeshCache`.
+pub fn load_scene(path: &str, mesh_cache: Option<&Path>) -> Result<Scene, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+    let file: Result<SceneFile, String> = if path.ends_with(".yaml") || path.ends_with(".yml") {
+        serde_yaml::from_str(&text).map_err(|e| e.to_string())
+    } else {
+        serde_json::from_str(&text).map_err(|e| e.to_string())
+    };
+    let file = file.map_err(|e| format!("cannot parse {}: {}", path, e))?;
+    build_scene(&file, mesh_cache)
+}
+
+pub fn build_scene(file: &SceneFile, mesh_cache: Option<&Path>) -> Result<Scene, String> {
+    let mut builder = SceneBuilder {
+        file,
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        camera: None,
+        assets: HashMap::new(),
+        building: Vec::new(),
+        bvh_stats: Vec::new(),
+        cache: mesh_cache.map(MeshCache::new),
+        lights: false,
+    };
+    let mut world = HittableList::new();
+    for desc in file.objects.iter() {
+        builder.add_object(desc, &mut world)?;
+    }
+    // Every top-level object gets its own id for the object ID pass, 0 is left for misses.
+    for (i, object) in world.objects.iter_mut().enumerate() {
+        *object = Arc::new(ObjectId::new(object.clone(), i as u32 + 1));
+    }
+    let camera = match builder.camera.take() {
+        Some(camera) => camera,
+        None => file.camera.clone().ok_or("the scene has no camera")?,
+    };
+    let mut warnings = match &builder.cache {
+        Some(cache) => cache.take_warnings(),
+        None => Vec::new(),
+    };
+    // Every emitter in the world is a light, unless the file lists the lights itself.
+    let mut lights = HittableList::new();
+    if file.lights.is_empty() {
+        let mut unsampled = Vec::new();
+        for light in world.objects.iter().flat_map(|o| o.clone().emitters()) {
+            if can_sample(&*light, camera.time0, camera.time1) {
+                lights.add(light);
+            } else {
+                unsampled.push(light);
+            }
+        }
+        if let Some(first) = unsampled.first() {
+            let at = match first.bounding_box(camera.time0, camera.time1) {
+                Some(b) => {
+                    let c = (b.minmum + b.maxmum) * 0.5;
+                    format!(" (the first around {:.1}, {:.1}, {:.1})", c.x, c.y, c.z)
+                }
+                None => String::new(),
+            };
+            warnings.push(format!(
+                "{} emitter(s) cannot be sampled and are left out of the light list{}",
+                unsampled.len(),
+                at
+            ));
+        }
+    } else {
+        builder.lights = true;
+        let mut listed = HittableList::new();
+        for desc in file.lights.iter() {
+            builder.add_object(desc, &mut listed)?;
+        }
+        // Light sampling starts from the lights' boxes, so an unbounded one can't take part.
+        let unbounded = listed
+            .objects
+            .iter()
+            .filter(|light| light.bounding_box(camera.time0, camera.time1).is_none())
+            .count();
+        if unbounded > 0 {
+            warnings.push(format!(
+                "{} light(s) have no bounding box and are left out of the light list",
+                unbounded
+            ));
+        }
+        for light in listed.objects.into_iter() {
+            if light.bounding_box(camera.time0, camera.time1).is_some() {
+                lights.add(light);
+            }
+        }
+    }
+    let punctual_lights = file
+        .punctual_lights
+        .iter()
+        .map(|desc| desc.build())
+        .collect::<Result<_, _>>()?;
+    let environment: Option<Arc<dyn Environment>> = match (&file.environment, &file.sky) {
+        (Some(_), Some(_)) => return Err("give either an environment or a sky".to_string()),
+        (Some(desc), None) => Some(Arc::new(
+            EnvironmentMap::load(&desc.file, desc.rotation, desc.intensity)
+                .map_err(|e| format!("environment: {}", e))?,
+        )),
+        (None, Some(desc)) => Some(Arc::new(desc.build()?)),
+        (None, None) => None,
+    };
+    let world = Bvh::new(&world.objects, camera.time0, camera.time1);
+    builder
+        .bvh_stats
+        .push(("world".to_string(), world.stats.clone()));
+    Ok(Scene {
+        world,
+        lights,
+        punctual_lights,
+        camera,
+        background: vec3(file.background),
+        environment,
+        bvh_stats: builder.bvh_stats,
+        warnings,
+    })
+}
+
+// Whether the light list can aim at `light`: from some point outside its box it has to
+// give a direction its own pdf accepts. Objects without `random` and `pdf_value` never do.
+fn can_sample(light: &dyn Hittable, time0: f64, time1: f64) -> bool {
+    let bbox = match light.bounding_box(time0, time1) {
+        Some(bbox) => bbox,
+        None => return false,
+    };
+    let center = (bbox.minmum + bbox.maxmum) * 0.5;
+    let reach = (bbox.maxmum - bbox.minmum).len() + 1.0;
+    let axes = [
+        Vec3::new(1.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        Vec3::new(0.0, 0.0, 1.0),
+    ];
+    axes.iter().flat_map(|a| vec![*a, -*a]).any(|a| {
+        let o = center + a * reach;
+        light.pdf_value(&o, &light.random(o)) > 0.0
+    })
+}
+
+struct SceneBuilder<'a> {
+    file: &'a SceneFile,
+    textures: HashMap<String, Arc<dyn Texture>>,
+    materials: HashMap<String, Arc<dyn Material>>,
+    // Taken from a glTF object asked to provide it.
+    camera: Option<CameraDesc>,
+    assets: HashMap<String, Arc<dyn Hittable>>,
+    // Assets being built, to catch one that instances itself.
+    building: Vec<String>,
+    bvh_stats: Vec<(String, BvhStats)>,
+    cache: Option<MeshCache>,
+    // Building the "lights" list, whose objects may leave out their material.
+    lights: bool,
+}
+
+impl<'a> SceneBuilder<'a> {
+    fn add_mesh(&mut self, label: &str, mesh: TriangleMesh, list: &mut HittableList) {
+        self.bvh_stats
+            .push((label.to_string(), mesh.bvh_stats().clone()));
+        list.add(Arc::new(mesh));
+    }
+
+    fn texture(&mut self, name: &str) -> Result<Arc<dyn Texture>, String> {
+        if let Some(tex) = self.textures.get(name) {
+            return Ok(tex.clone());
+        }
+        let desc = self
+            .file
+            .textures
+            .get(name)
+            .ok_or_else(|| format!("unknown texture \"{}\"", name))?;
+        let tex: Arc<dyn Texture> = match desc {
+            TextureDesc::Solid { color } => Arc::new(SolidColor::new(vec3(*color))),
+            TextureDesc::Checker { even, odd } => {
+                Arc::new(CheckerTexture::new(vec3(*even), vec3(*odd)))
+            }
+            TextureDesc::Noise { scale } => Arc::new(NoiseTexture::new1(*scale)),
+            TextureDesc::Image { file } => Arc::new(
+                ImageTexture::load(file).map_err(|e| format!("texture \"{}\": {}", name, e))?,
+            ),
+        };
+        self.textures.insert(name.to_string(), tex.clone());
+        Ok(tex)
+    }
+
+    fn color_or_texture(&mut self, c: &ColorOrTexture) -> Result<Arc<dyn Texture>, String> {
+        match c {
+            ColorOrTexture::Color(color) => Ok(Arc::new(SolidColor::new(vec3(*color)))),
+            ColorOrTexture::Texture(name) => self.texture(name),
+        }
+    }
+
+    fn material(&mut self, name: &Option<String>) -> Result<Arc<dyn Material>, String> {
+        let name = match name {
+            Some(name) => name,
+            // Objects in the lights list are only sampled, never shaded.
+            None if self.lights => return Ok(Arc::new(Metal::new(Vec3::zero(), 0.0))),
+            None => return Err("the object has no material".to_string()),
+        };
+        if let Some(mat) = self.materials.get(name) {
+            return Ok(mat.clone());
+        }
+        let desc = self
+            .file
+            .materials
+            .get(name)
+            .ok_or_else(|| format!("unknown material \"{}\"", name))?;
+        let mat: Arc<dyn Material> = match desc {
+            MaterialDesc::Lambertian { albedo } => {
+                Arc::new(Lambertian::new1(self.color_or_texture(albedo)?))
+            }
+            MaterialDesc::Metal { albedo, fuzz } => Arc::new(Metal::new(vec3(*albedo), *fuzz)),
+            MaterialDesc::Dielectric { ir } => Arc::new(Dielectric::new(*ir)),
+            MaterialDesc::DiffuseLight { emit } => {
+                Arc::new(DiffuseLight::new(self.color_or_texture(emit)?))
+            }
+            MaterialDesc::Isotropic { albedo } => {
+                Arc::new(Isotropic::new1(self.color_or_texture(albedo)?))
+            }
+        };
+        self.materials.insert(name.clone(), mat.clone());
+        Ok(mat)
+    }
+
+    fn asset(&mut self, name: &str) -> Result<Arc<dyn Hittable>, String> {
+        if let Some(asset) = self.assets.get(name) {
+            return Ok(asset.clone());
+        }
+        let desc = self
+            .file
+            .assets
+            .get(name)
+            .ok_or_else(|| format!("unknown asset \"{}\"", name))?;
+        if self.building.iter().any(|n| n == name) {
+            return Err(format!("asset \"{}\" contains an instance of itself", name));
+        }
+        self.building.push(name.to_string());
+        let asset = self
+            .object(desc)
+            .map_err(|e| format!("asset \"{}\": {}", name, e));
+        self.building.pop();
+        let asset = asset?;
+        self.assets.insert(name.to_string(), asset.clone());
+        Ok(asset)
+    }
+
+    // Mesh files bring their own materials, so no name means no override.
+    fn mesh_material(
+        &mut self,
+        name: &Option<String>,
+    ) -> Result<Option<Arc<dyn Material>>, String> {
+        match name {
+            Some(_) => self.material(name).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn add_object(&mut self, desc: &ObjectDesc, list: &mut HittableList) -> Result<(), String> {
+        match desc {
+            ObjectDesc::Obj {
+                file,
+                scale,
+                transform,
+                material,
+            } => {
+                let mat = self.mesh_material(material)?;
+                let transform =
+                    placement(transform) * Mat4::scale(Vec3::new(*scale, *scale, *scale));
+                for mesh in load_obj_cached(file, &transform, mat, self.cache.as_ref())? {
+                    self.add_mesh(file, mesh, list);
+                }
+                Ok(())
+            }
+            ObjectDesc::Gltf {
+                file,
+                transform,
+                material,
+                camera,
+            } => {
+                let mat = self.mesh_material(material)?;
+                let gltf = load_gltf(file, &placement(transform), mat)?;
+                if *camera {
+                    let c = gltf
+                        .camera
+                        .ok_or_else(|| format!("{} has no perspective camera", file))?;
+                    self.camera = Some(CameraDesc {
+                        lookfrom: [c.lookfrom.x, c.lookfrom.y, c.lookfrom.z],
+                        lookat: [c.lookat.x, c.lookat.y, c.lookat.z],
+                        vup: [c.vup.x, c.vup.y, c.vup.z],
+                        vfov: c.vfov,
+                        aspect_ratio: c.aspect_ratio.unwrap_or_else(default_aspect_ratio),
+                        aperture: 0.0,
+                        focus_dist: default_focus_dist(),
+                        time0: 0.0,
+                        time1: default_time1(),
+                    });
+                }
+                for mesh in gltf.meshes {
+                    self.add_mesh(file, mesh, list);
+                }
+                Ok(())
+            }
+            ObjectDesc::Ply {
+                file,
+                transform,
+                material,
+            } => {
+                let mat = self.mesh_material(material)?;
+                let mesh = load_ply_cached(file, &placement(transform), mat, self.cache.as_ref())?;
+                self.add_mesh(file, mesh, list);
+                Ok(())
+            }
+            ObjectDesc::Stl {
+                file,
+                transform,
+                material,
+            } => {
+                let mat = self.mesh_material(material)?;
+                let mesh = load_stl_cached(file, &placement(transform), mat, self.cache.as_ref())?;
+                self.add_mesh(file, mesh, list);
+                Ok(())
+            }
+            ObjectDesc::Preset { name } => match name.as_str() {
+                "random_scene" => {
+                    random_scene(list);
+                    Ok(())
+                }
+                "final_scene" => {
+                    final_scene(list);
+                    Ok(())
+                }
+                _ => Err(format!("unknown preset \"{}\"", name)),
+            },
+            _ => {
+                let object = self.object(desc)?;
+                list.add(object);
+                Ok(())
+            }
+        }
+    }
+
+    fn object(&mut self, desc: &ObjectDesc) -> Result<Arc<dyn Hittable>, String> {
+        let object: Arc<dyn Hittable> = match desc {
+            ObjectDesc::Sphere {
+                center,
+                radius,
+                material,
+            } => Arc::new(Sphere::new(
+                vec3(*center),
+                *radius,
+                self.material(material)?,
+            )),
+            ObjectDesc::MovingSphere {
+                center0,
+                center1,
+                time0,
+                time1,
+                radius,
+                material,
+            } => Arc::new(MovingSphere::new(
+                vec3(*center0),
+                vec3(*center1),
+                *time0,
+                *time1,
+                *radius,
+                self.material(material)?,
+            )),
+            ObjectDesc::XyRect {
+                x0,
+                x1,
+                y0,
+                y1,
+                k,
+                material,
+            } => Arc::new(XyRect::new(
+                *x0,
+                *x1,
+                *y0,
+                *y1,
+                *k,
+                self.material(material)?,
+            )),
+            ObjectDesc::XzRect {
+                x0,
+                x1,
+                z0,
+                z1,
+                k,
+                material,
+            } => Arc::new(XzRect::new(
+                *x0,
+                *x1,
+                *z0,
+                *z1,
+                *k,
+                self.material(material)?,
+            )),
+            ObjectDesc::YzRect {
+                y0,
+                y1,
+                z0,
+                z1,
+                k,
+                material,
+            } => Arc::new(YzRect::new(
+                *y0,
+                *y1,
+                *z0,
+                *z1,
+                *k,
+                self.material(material)?,
+            )),
+            ObjectDesc::Box { p0, p1, material } => {
+                Arc::new(Box_::new(vec3(*p0), vec3(*p1), self.material(material)?))
+            }
+            ObjectDesc::Triangle { a, b, c, material } => {
+                Arc::new(TriangleMesh::new(MeshData::new(
+                    vec![vec3(*a), vec3(*b), vec3(*c)],
+                    vec![[0, 1, 2]],
+                    self.material(material)?,
+                ))?)
+            }
+            ObjectDesc::ConstantMedium {
+                boundary,
+                density,
+                albedo,
+            } => Arc::new(ConstantMedium::new(
+                self.object(boundary)?,
+                *density,
+                self.color_or_texture(albedo)?,
+            )),
+            ObjectDesc::Translate { offset, object } => {
+                Arc::new(Translate::new(self.object(object)?, vec3(*offset)))
+            }
+            ObjectDesc::RotateY { angle, object } => {
+                Arc::new(RotateY::new(self.object(object)?, *angle))
+            }
+            ObjectDesc::Transform { transform, object } => {
+                Arc::new(Transform::new(self.object(object)?, transform.matrix())?)
+            }
+            ObjectDesc::FlipFace { object } => Arc::new(FlipFace::new(self.object(object)?)),
+            ObjectDesc::Instance {
+                asset,
+                transform,
+                material,
+            } => {
+                let material = self.mesh_material(material)?;
+                Arc::new(Instance::new(
+                    self.asset(asset)?,
+                    placement(transform),
+                    material,
+                )?)
+            }
+            ObjectDesc::Bvh { .. }
+            | ObjectDesc::Obj { .. }
+            | ObjectDesc::Gltf { .. }
+            | ObjectDesc::Ply { .. }
+            | ObjectDesc::Stl { .. }
+            | ObjectDesc::Preset { .. } => {
+                let mut group = HittableList::new();
+                let mut options = BvhOptions::default();
+                match desc {
+                    ObjectDesc::Bvh {
+                        objects,
+                        max_leaf_size,
+                    } => {
+                        for child in objects.iter() {
+                            self.add_object(child, &mut group)?;
+                        }
+                        if let Some(n) = max_leaf_size {
+                            if *n == 0 {
+                                return Err("max_leaf_size must be positive".to_string());
+                            }
+                            options.max_leaf_size = *n;
+                        }
+                    }
+                    _ => self.add_object(desc, &mut group)?,
+                }
+                if group.objects.is_empty() {
+                    return Err("empty object group".to_string());
+                }
+                if group.objects.len() == 1 {
+                    return Ok(group.objects[0].clone());
+                }
+                let bvh = Bvh::with_options(&group.objects, 0.0, 1.0, &options);
+                self.bvh_stats
+                    .push(("group".to_string(), bvh.stats.clone()));
+                Arc::new(bvh)
+            }
+        };
+        Ok(object)
+    }
+}
+
+pub fn random_scene(world: &mut HittableList) {
+    let checker = Arc::new(CheckerTexture::new(
+        Vec3::new(0.2, 0.3, 0.1),
+        Vec3::new(0.9, 0.9, 0.9),
+    ));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new1(checker)),
+    )));
+
+    for a in -11..11 {
+        for b in -11..11 {
+            let choose_mat = random_double(1.0, 100.0);
+            let center = Vec3::new(
+                a as f64 + 0.9 * random_double(1.0, 100.0),
+                0.2,
+                b as f64 + 0.9 * random_double(1.0, 100.0),
+            );
+            if (center - Vec3::new(4.0, 0.2, 0.0)).len() > 0.9 {
+                if choose_mat < 0.8 {
+                    let albedo1 = Vec3::random();
+                    let albedo2 = Vec3::random();
+                    let albedo = Vec3::new(
+                        albedo1.x * albedo2.x,
+                        albedo1.y * albedo2.y,
+                        albedo1.z * albedo2.z,
+                    );
+                    let sphere_material = Arc::new(Lambertian::new(albedo));
+                    let center2 = center + Vec3::new(0.0, random_double2(0.0, 0.5), 0.0);
+                    world.add(Arc::new(MovingSphere::new(
+                        center,
+                        center2,
+                        0.0,
+                        1.0,
+                        0.2,
+                        sphere_material,
+                    )));
+                } else if choose_mat < 0.95 {
+                    let albedo = Vec3::random_(0.5, 1.0);
+                    let fuzz = random_double2(0.0, 0.5);
+                    let sphere_material = Arc::new(Metal::new(albedo, fuzz));
+                    world.add(Arc::new(Sphere::new(center, 0.2, sphere_material)));
+                } else {
+                    let sphere_material = Arc::new(Dielectric::new(1.5));
+                    world.add(Arc::new(Sphere::new(center, 0.2, sphere_material)));
+                }
+            }
+        }
+    }
+    let material1 = Arc::new(Dielectric::new(1.5));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, 1.0, 0.0),
+        1.0,
+        material1,
+    )));
+
+    let material2 = Arc::new(Lambertian::new(Vec3::new(0.4, 0.2, 0.1)));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(-4.0, 1.0, 0.0),
+        1.0,
+        material2,
+    )));
+
+    let material3 = Arc::new(Metal::new(Vec3::new(0.7, 0.6, 0.5), 0.0));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(4.0, 1.0, 0.0),
+        1.0,
+        material3,
+    )));
+}
+
+pub fn final_scene(world: &mut HittableList) {
+    let mut boxes1 = HittableList::new();
+    let ground = Arc::new(Lambertian::new(Vec3::new(0.48, 0.83, 0.53)));
+    let boxes_per_side = 20;
+
+    for i in 0..boxes_per_side {
+        for j in 0..boxes_per_side {
+            let w = 100.0;
+            let x0 = -1000.0 + i as f64 * w;
+            let z0 = -1000.0 + j as f64 * w;
+            let y0 = 0.0;
+            let x1 = x0 + w;
+            let y1 = random_double(1.0, 101.0) * 100.0 + 1.0;
+            let z1 = z0 + w;
+
+            boxes1.add(Arc::new(Box_::new(
+                Vec3::new(x0, y0, z0),
+                Vec3::new(x1, y1, z1),
+                ground.clone(),
+            )));
+        }
+    }
+    world.add(Arc::new(Bvh::new(&boxes1.objects, 0.0, 1.0)));
+
+    let light = Arc::new(DiffuseLight::new1(Vec3::new(7.0, 7.0, 7.0)));
+    world.add(Arc::new(XzRect::new(
+        123.0, 423.0, 147.0, 412.0, 554.0, light,
+    )));
+
+    let center1 = Vec3::new(400.0, 400.0, 200.0);
+    let center2 = center1 + Vec3::new(30.0, 0.0, 0.0);
+    let moving_sphere_material = Arc::new(Lambertian::new(Vec3::new(0.7, 0.3, 0.1)));
+    world.add(Arc::new(MovingSphere::new(
+        center1,
+        center2,
+        0.0,
+        1.0,
+        50.0,
+        moving_sphere_material,
+    )));
+
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(260.0, 150.0, 45.0),
+        50.0,
+        Arc::new(Dielectric::new(1.5)),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, 150.0, 145.0),
+        50.0,
+        Arc::new(Metal::new(Vec3::new(0.8, 0.8, 0.9), 1.0)),
+    )));
+
+    let boundary = Arc::new(Sphere::new(
+        Vec3::new(360.0, 150.0, 145.0),
+        70.0,
+        Arc::new(Dielectric::new(1.5)),
+    ));
+    world.add(boundary.clone());
+    world.add(Arc::new(ConstantMedium::new1(
+        boundary,
+        0.2,
+        Vec3::new(0.2, 0.4, 0.9),
+    )));
+
+    let boundary = Arc::new(Sphere::new(
+        Vec3::zero(),
+        5000.0,
+        Arc::new(Dielectric::new(1.5)),
+    ));
+    world.add(Arc::new(ConstantMedium::new1(
+        boundary,
+        0.0001,
+        Vec3::ones(),
+    )));
+
+    let emat = Arc::new(Lambertian::new1(Arc::new(ImageTexture::new(
+        "earthmap.jpg",
+    ))));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(400.0, 200.0, 400.0),
+        100.0,
+        emat,
+    )));
+
+    let pertext = Arc::new(NoiseTexture::new1(0.1));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(220.0, 280.0, 300.0),
+        80.0,
+        Arc::new(Lambertian::new1(pertext)),
+    )));
+    let mut boxes2 = HittableList::new();
+    let white = Arc::new(Lambertian::new(Vec3::new(0.73, 0.73, 0.73)));
+    let ns = 1000;
+    for _j in 0..ns {
+        boxes2.add(Arc::new(Sphere::new(
+            Vec3::new(
+                random_double2(0.0, 165.0),
+                random_double2(0.0, 165.0),
+                random_double2(0.0, 165.0),
+            ),
+            10.0,
+            white.clone(),
+        )));
+    }
+    let tp = Arc::new(Bvh::new(&boxes2.objects, 0.0, 1.0));
+    world.add(Arc::new(Translate::new(
+        Arc::new(RotateY::new(tp, 15.0)),
+        Vec3::new(-100.0, 270.0, 395.0),
+    )));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lights_are_found_in_the_world() {
+        let file: SceneFile = serde_json::from_str(
+            r#"{
+                "camera": { "lookfrom": [0, 0, 10], "lookat": [0, 0, 0] },
+                "materials": {
+                    "lamp": { "type": "diffuse_light", "emit": [4, 4, 4] },
+                    "grey": { "type": "lambertian", "albedo": [0.5, 0.5, 0.5] }
+                },
+                "assets": {
+                    "pair": { "type": "bvh", "objects": [
+                        { "type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "lamp" },
+                        { "type": "sphere", "center": [3, 0, 0], "radius": 1, "material": "grey" }
+                    ] }
+                },
+                "objects": [
+                    { "type": "flip_face", "object": { "type": "xz_rect", "x0": 0, "x1": 1,
+                      "z0": 0, "z1": 1, "k": 5, "material": "lamp" } },
+                    { "type": "box", "p0": [0, 0, 0], "p1": [1, 1, 1], "material": "grey" },
+                    { "type": "instance", "asset": "pair",
+                      "transform": { "translate": [0, 10, 0] } },
+                    { "type": "instance", "asset": "pair", "material": "grey" },
+                    { "type": "rotate_y", "angle": 30, "object": { "type": "sphere",
+                      "center": [0, -5, 0], "radius": 1, "material": "lamp" } },
+                    { "type": "moving_sphere", "center0": [5, 0, 0], "center1": [6, 0, 0],
+                      "time0": 0, "time1": 1, "radius": 1, "material": "lamp" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let scene = build_scene(&file, None).unwrap();
+        // The rectangle, the instanced and the rotated sphere. The moving sphere's pdf
+        // would need the time of the ray.
+        assert_eq!(scene.lights.objects.len(), 3);
+        assert_eq!(scene.warnings.len(), 1);
+        assert!(scene.warnings[0].starts_with("1 emitter(s)"));
+        // The instanced lamp is sampled where the instance put it.
+        let o = Vec3::new(0.0, 20.0, 0.0);
+        let lamp = &scene.lights.objects[1];
+        let dir = lamp.random(o);
+        assert!(dir.y < 0.0 && dir.x.abs() < 0.2 * -dir.y);
+        assert!(lamp.pdf_value(&o, &dir) > 0.0);
+    }
+
+    #[test]
+    fn test_emissive_instances_of_groups_are_sampled() {
+        let file: SceneFile = serde_json::from_str(
+            r#"{
+                "camera": { "lookfrom": [0, 0, 10], "lookat": [0, 0, 0] },
+                "materials": {
+                    "lamp": { "type": "diffuse_light", "emit": [4, 4, 4] },
+                    "grey": { "type": "lambertian", "albedo": [0.5, 0.5, 0.5] }
+                },
+                "assets": {
+                    "fixture": { "type": "bvh", "objects": [
+                        { "type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "grey" },
+                        { "type": "xz_rect", "x0": 2, "x1": 4, "z0": -1, "z1": 1, "k": 0,
+                          "material": "grey" },
+                        { "type": "box", "p0": [-4, -1, -1], "p1": [-2, 1, 1], "material": "grey" }
+                    ] }
+                },
+                "objects": [
+                    { "type": "instance", "asset": "fixture", "material": "lamp",
+                      "transform": { "translate": [0, 2, 0] } }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let scene = build_scene(&file, None).unwrap();
+        assert_eq!(scene.lights.objects.len(), 1);
+        assert!(scene.warnings.is_empty(), "{:?}", scene.warnings);
+
+        // Every part gets samples, and the pdf integrates to one over the sphere.
+        let light = &scene.lights.objects[0];
+        let o = Vec3::new(0.5, 9.0, 3.0);
+        let mut parts = [0; 3];
+        for _ in 0..3000 {
+            let d = light.random(o);
+            assert!(light.pdf_value(&o, &d) > 0.0);
+            let p = o + d;
+            parts[if p.x > 1.5 {
+                1
+            } else if p.x < -1.5 {
+                2
+            } else {
+                0
+            }] += 1;
+        }
+        assert!(parts.iter().all(|n| *n > 100), "{:?}", parts);
+        let n = 500;
+        let mut sum = 0.0;
+        for i in 0..n {
+            for j in 0..2 * n {
+                let theta = (i as f64 + 0.5) / n as f64 * std::f64::consts::PI;
+                let phi = (j as f64 + 0.5) / (2 * n) as f64 * 2.0 * std::f64::consts::PI;
+                let d = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                let d_omega = theta.sin() * (std::f64::consts::PI / n as f64).powi(2);
+                sum += light.pdf_value(&o, &d) * d_omega;
+            }
+        }
+        assert!((sum - 1.0).abs() < 0.02, "{}", sum);
+    }
+
+    #[test]
+    fn test_bad_objects_are_errors() {
+        let build = |text: &str| build_scene(&serde_json::from_str(text).unwrap(), None);
+        let camera = r#""camera": { "lookfrom": [0, 0, 10], "lookat": [0, 0, 0] }"#;
+        let sphere = r#"{ "type": "sphere", "center": [0, 0, 0], "radius": 1 }"#;
+
+        // Only the lights list can do without materials.
+        let err = build(&format!(r#"{{ {}, "objects": [{}] }}"#, camera, sphere));
+        assert_eq!(err.err().unwrap(), "the object has no material");
+        let scene = build(&format!(
+            r#"{{ {}, "objects": [], "lights": [{}] }}"#,
+            camera, sphere
+        ));
+        assert_eq!(scene.unwrap().lights.objects.len(), 1);
+
+        // An image that doesn't decode is reported, not a panic.
+        let file = std::env::temp_dir().join("raytracer_corrupt_texture.png");
+        fs::write(&file, b"not a png").unwrap();
+        let err = build(&format!(
+            r#"{{ {}, "textures": {{ "t": {{ "type": "image", "file": {:?} }} }},
+                "materials": {{ "m": {{ "type": "lambertian", "albedo": "t" }} }},
+                "objects": [{{ "type": "sphere", "center": [0, 0, 0], "radius": 1,
+                    "material": "m" }}] }}"#,
+            camera,
+            file.to_str().unwrap()
+        ));
+        fs::remove_file(&file).unwrap();
+        assert!(err.err().unwrap().starts_with("texture \"t\""));
+    }
+
+    #[test]
+    fn test_yaml_scenes_are_loaded() {
+        let yaml = "
+camera: { lookfrom: [0, 0, 10], lookat: [0, 0, 0] }
+materials:
+  lamp: { type: diffuse_light, emit: [4, 4, 4] }
+objects:
+  - { type: sphere, center: [0, 0, 0], radius: 1, material: lamp }
+";
+        let file = std::env::temp_dir().join("raytracer_test_scene.yaml");
+        fs::write(&file, yaml).unwrap();
+        let scene = load_scene(file.to_str().unwrap(), None);
+        fs::remove_file(&file).unwrap();
+        assert_eq!(scene.unwrap().lights.objects.len(), 1);
+
+        // The same text is not JSON.
+        let file = std::env::temp_dir().join("raytracer_test_scene.json");
+        fs::write(&file, yaml).unwrap();
+        let err = load_scene(file.to_str().unwrap(), None).err().unwrap();
+        fs::remove_file(&file).unwrap();
+        assert!(err.starts_with("cannot parse"), "{}", err);
+    }
+}
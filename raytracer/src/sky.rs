@@ -0,0 +1,224 @@
+use std::f64::consts::PI;
+
+use crate::{
+    environment::{lat_long_direction, Environment, EnvironmentMap},
+    onb::Onb,
+    sampler::{sample_1d, sample_2d},
+    Vec3,
+};
+
+// Angular radius of the sun's disk.
+const SUN_RADIUS: f64 = 0.0046;
+// Resolution of the table the sky is importance-sampled from.
+const TABLE_WIDTH: usize = 128;
+const TABLE_HEIGHT: usize = 64;
+
+// Daylight after Preetham, Shirley and Smits, "A Practical Analytic Model for Daylight"
+// (1999): the clear sky for a given turbidity and sun position, plus the sun's disk.
+// Radiance is in thousands of cd/m^2 times `intensity`. Below the horizon the horizon's
+// colour carries on, so a ground that stops short doesn't leave a dark band.
+pub struct Sky {
+    sun_dir: Vec3,
+    sun_radiance: Vec3,
+    // Perez coefficients A to E and the zenith value, for luminance Y and the x and y
+    // chromaticities.
+    perez: [[f64; 5]; 3],
+    zenith: [f64; 3],
+    intensity: f64,
+    // The sky without the sun, tabulated to draw directions from.
+    table: EnvironmentMap,
+    // How often `random` aims at the sun instead of the sky.
+    sun_probability: f64,
+}
+
+impl Sky {
+    // `elevation` is the sun's height above the horizon and `azimuth` turns it from -z
+    // towards +x, both in degrees. Turbidity runs from about 2 (very clear) to 10 (hazy).
+    pub fn new(turbidity: f64, elevation: f64, azimuth: f64, intensity: f64) -> Self {
+        let (elevation, azimuth) = (elevation.to_radians(), azimuth.to_radians());
+        let sun_dir = Vec3::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            -elevation.cos() * azimuth.cos(),
+        );
+        let t = turbidity;
+        let theta_s = PI / 2.0 - elevation;
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let cubic =
+            |c: [f64; 4]| c[0] * theta_s.powi(3) + c[1] * theta_s.powi(2) + c[2] * theta_s + c[3];
+        let x_zenith = t * t * cubic([0.00166, -0.00375, 0.00209, 0.0])
+            + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+            + cubic([0.11693, -0.21196, 0.06052, 0.25886]);
+        let y_zenith = t * t * cubic([0.00275, -0.00610, 0.00317, 0.0])
+            + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+            + cubic([0.15346, -0.26756, 0.06670, 0.26688]);
+        let luminance_zenith = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+
+        let mut sky = Self {
+            sun_dir,
+            sun_radiance: sun_radiance(t, theta_s) * intensity,
+            perez,
+            zenith: [luminance_zenith, x_zenith, y_zenith],
+            intensity,
+            table: EnvironmentMap::new(1, 1, vec![Vec3::zero()], 0.0, 1.0),
+            sun_probability: 0.0,
+        };
+        let pixels = (0..TABLE_HEIGHT)
+            .flat_map(|y| (0..TABLE_WIDTH).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let u = (x as f64 + 0.5) / TABLE_WIDTH as f64;
+                let v = (y as f64 + 0.5) / TABLE_HEIGHT as f64;
+                sky.sky_radiance(&lat_long_direction(u, v))
+            })
+            .collect();
+        sky.table = EnvironmentMap::new(TABLE_WIDTH, TABLE_HEIGHT, pixels, 0.0, 1.0);
+        // Split the samples by how much light each part sends, but keep some for both.
+        let sun_power = sky.sun_radiance.luminance() * sun_solid_angle();
+        sky.sun_probability = (sun_power / (sun_power + sky.table.power())).clamp(0.1, 0.9);
+        sky
+    }
+
+    fn sky_radiance(&self, dir: &Vec3) -> Vec3 {
+        let dir = Vec3::new(dir.x, dir.y.max(0.0), dir.z).unit();
+        let cos_theta = dir.y;
+        let gamma = (dir * self.sun_dir).clamp(-1.0, 1.0).acos();
+        let theta_s = self.sun_dir.y.clamp(-1.0, 1.0).acos();
+        let mut xy_y = [0.0; 3];
+        for (i, value) in xy_y.iter_mut().enumerate() {
+            let c = &self.perez[i];
+            *value = self.zenith[i] * perez(c, cos_theta, gamma) / perez(c, 1.0, theta_s);
+        }
+        let [big_y, x, y] = xy_y;
+        xyy_to_rgb(x, y, big_y) * self.intensity
+    }
+}
+
+impl Environment for Sky {
+    fn radiance(&self, dir: &Vec3) -> Vec3 {
+        let dir = dir.unit();
+        let mut l = self.sky_radiance(&dir);
+        if dir * self.sun_dir > SUN_RADIUS.cos() {
+            l += self.sun_radiance;
+        }
+        l
+    }
+
+    fn random(&self) -> Vec3 {
+        if sample_1d() >= self.sun_probability {
+            return self.table.random();
+        }
+        // Uniform over the cone the disk subtends.
+        let (r1, r2) = sample_2d();
+        let z = 1.0 - r1 * (1.0 - SUN_RADIUS.cos());
+        let phi = 2.0 * PI * r2;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        Onb::build_from_w(&self.sun_dir).local1(&Vec3::new(r * phi.cos(), r * phi.sin(), z))
+    }
+
+    fn pdf_value(&self, dir: &Vec3) -> f64 {
+        let mut pdf = (1.0 - self.sun_probability) * self.table.pdf_value(dir);
+        if dir.unit() * self.sun_dir > SUN_RADIUS.cos() {
+            pdf += self.sun_probability / sun_solid_angle();
+        }
+        pdf
+    }
+
+    fn power(&self) -> f64 {
+        self.sun_radiance.luminance() * sun_solid_angle() + self.table.power()
+    }
+}
+
+fn sun_solid_angle() -> f64 {
+    2.0 * PI * (1.0 - SUN_RADIUS.cos())
+}
+
+// The Perez sky distribution at zenith angle acos(cos_theta), gamma away from the sun.
+fn perez(c: &[f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+    (1.0 + c[0] * (c[1] / cos_theta.max(1e-4)).exp())
+        * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos() * gamma.cos())
+}
+
+// CIE xyY to linear sRGB, clipping colours outside its gamut.
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> Vec3 {
+    if y <= 0.0 {
+        return Vec3::zero();
+    }
+    let cx = x / y * luminance;
+    let cz = (1.0 - x - y) / y * luminance;
+    Vec3::new(
+        (3.2406 * cx - 1.5372 * luminance - 0.4986 * cz).max(0.0),
+        (-0.9689 * cx + 1.8758 * luminance + 0.0415 * cz).max(0.0),
+        (0.0557 * cx - 0.2040 * luminance + 1.0570 * cz).max(0.0),
+    )
+}
+
+// The sun's disk seen through the atmosphere: about 1.6e6 thousand cd/m^2 overhead, dimmed
+// and reddened by Rayleigh and aerosol extinction along the relative air mass, at a
+// wavelength per channel.
+fn sun_radiance(turbidity: f64, theta_s: f64) -> Vec3 {
+    let air_mass = 1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_s.to_degrees()).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+    let channel = |lambda: f64| {
+        let rayleigh = (-0.008735 * lambda.powf(-4.08) * air_mass).exp();
+        let aerosol = (-beta * lambda.powf(-1.3) * air_mass).exp();
+        2.0e6 * rayleigh * aerosol
+    };
+    Vec3::new(channel(0.68), channel(0.55), channel(0.44))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sky() {
+        let sky = Sky::new(3.0, 30.0, 45.0, 1.0);
+        // Blue overhead, the horizon's colour below it, the sun far brighter than the sky.
+        let zenith = sky.radiance(&Vec3::new(0.0, 1.0, 0.0));
+        assert!(zenith.z > zenith.x && zenith.x > 0.0, "{:?}", zenith);
+        let horizon = sky.radiance(&Vec3::new(0.3, 0.0, 0.2));
+        assert!((sky.radiance(&Vec3::new(0.3, -0.1, 0.2)) - horizon).len() < 1e-9);
+        let sun = sky.radiance(&sky.sun_dir);
+        assert!(sun.x > 1e4 * zenith.x, "{:?}", sun);
+        assert!(sun.x > sun.z);
+
+        // Sampling finds the sun about as often as it should, and its pdf agrees.
+        let mut at_sun = 0;
+        for _ in 0..2000 {
+            let d = sky.random();
+            assert!(sky.pdf_value(&d) > 0.0);
+            if d * sky.sun_dir > SUN_RADIUS.cos() {
+                at_sun += 1;
+            }
+        }
+        let expected = 2000.0 * sky.sun_probability;
+        assert!(
+            (at_sun as f64 - expected).abs() < 0.1 * expected,
+            "{}",
+            at_sun
+        );
+    }
+}
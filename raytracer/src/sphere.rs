@@ -4,7 +4,7 @@ use std::sync::Arc;
 
 use crate::aabb::AABB;
 use crate::onb::Onb;
-use crate::rtweekend::random_double;
+use crate::sampler::sample_2d;
 use crate::vec3::Vec3;
 use crate::{
     hittable::{HitRecord, Hittable},
@@ -101,11 +101,26 @@ impl Hittable for Sphere {
         let uvw = Onb::build_from_w(&direction);
         uvw.local1(&random_to_sphere(self.radius, distance_squared))
     }
+
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    fn power(&self) -> f64 {
+        self.mat_ptr.emitted_power(self.area())
+    }
+
+    fn emitters(self: Arc<Self>) -> Vec<Arc<dyn Hittable>> {
+        if self.mat_ptr.is_emissive() {
+            vec![self]
+        } else {
+            Vec::new()
+        }
+    }
 }
 
 pub fn random_to_sphere(radius: f64, distance_squred: f64) -> Vec3 {
-    let r1 = random_double(0.0, 100.0);
-    let r2 = random_double(0.0, 100.0);
+    let (r1, r2) = sample_2d();
     let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squred).sqrt() - 1.0);
     let phi = 2.0 * PI * r1;
     let x = phi.cos() * (1.0 - z * z).sqrt();
@@ -198,4 +213,20 @@ impl Hittable for MovingSphere {
         Some(output_box)
         //改成option
     }
+
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    fn power(&self) -> f64 {
+        self.mat_ptr.emitted_power(self.area())
+    }
+
+    fn emitters(self: Arc<Self>) -> Vec<Arc<dyn Hittable>> {
+        if self.mat_ptr.is_emissive() {
+            vec![self]
+        } else {
+            Vec::new()
+        }
+    }
 }
@@ -0,0 +1,184 @@
+use std::{fs, sync::Arc};
+
+use crate::{
+    cache::MeshCache,
+    materia::{Lambertian, Material},
+    matrix::Mat4,
+    mesh::{MeshData, TriangleMesh},
+    Vec3,
+};
+
+// Loads an STL file, ASCII or binary, as one mesh. STL stores every facet with its own
+// three vertices and no attributes, so the mesh is flat shaded; without a material it is
+// light grey Lambertian.
+pub fn load_stl(
+    file: &str,
+    transform: &Mat4,
+    material: Option<Arc<dyn Material>>,
+) -> Result<TriangleMesh, String> {
+    load_stl_cached(file, transform, material, None)
+}
+
+// As `load_stl`, reusing the mesh built by an earlier run when `cache` holds it.
+pub fn load_stl_cached(
+    file: &str,
+    transform: &Mat4,
+    material: Option<Arc<dyn Material>>,
+    cache: Option<&MeshCache>,
+) -> Result<TriangleMesh, String> {
+    let bytes = fs::read(file).map_err(|e| format!("cannot read {}: {}", file, e))?;
+    let key = MeshCache::key(&[&bytes], transform);
+    if let Some(cached) = cache
+        .and_then(|c| c.load(file, key))
+        .and_then(|mut v| v.pop())
+    {
+        let material = material.unwrap_or_else(default_material);
+        return Ok(cached.into_mesh(material));
+    }
+    let mut data = parse_stl(&bytes, material).map_err(|e| format!("{}: {}", file, e))?;
+    data.transform(transform)
+        .map_err(|e| format!("{}: {}", file, e))?;
+    let mesh = TriangleMesh::new(data).map_err(|e| format!("{}: {}", file, e))?;
+    if let Some(cache) = cache {
+        cache.store(file, key, &[(&mesh, "")]);
+    }
+    Ok(mesh)
+}
+
+pub fn parse_stl(bytes: &[u8], material: Option<Arc<dyn Material>>) -> Result<MeshData, String> {
+    // Binary files may also begin with "solid", so the size is the better test: an 80 byte
+    // header, a triangle count and 50 bytes per triangle.
+    let binary = bytes.len() >= 84 && {
+        let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+        bytes.len() == 84 + 50 * count
+    };
+    let positions = if binary {
+        binary_positions(bytes)
+    } else if bytes.starts_with(b"solid") {
+        ascii_positions(bytes)?
+    } else {
+        return Err("neither an ASCII nor a binary STL file".to_string());
+    };
+    if positions.is_empty() {
+        return Err("no facets".to_string());
+    }
+    let indices = (0..positions.len() / 3)
+        .map(|i| [3 * i, 3 * i + 1, 3 * i + 2])
+        .collect();
+    let material = material.unwrap_or_else(default_material);
+    Ok(MeshData::new(positions, indices, material))
+}
+
+fn default_material() -> Arc<dyn Material> {
+    Arc::new(Lambertian::new(Vec3::new(0.73, 0.73, 0.73)))
+}
+
+fn binary_positions(bytes: &[u8]) -> Vec<Vec3> {
+    let float = |at: usize| {
+        f32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]) as f64
+    };
+    let mut positions = Vec::new();
+    // Each record is a normal, three vertices and a 2 byte attribute count.
+    for record in (84..bytes.len()).step_by(50) {
+        for v in 0..3 {
+            let at = record + 12 + 12 * v;
+            positions.push(Vec3::new(float(at), float(at + 4), float(at + 8)));
+        }
+    }
+    positions
+}
+
+fn ascii_positions(bytes: &[u8]) -> Result<Vec<Vec3>, String> {
+    let text = std::str::from_utf8(bytes).map_err(|_| "the ASCII STL is not text")?;
+    let mut positions = Vec::new();
+    let mut in_facet = 0;
+    for (i, line) in text.lines().enumerate() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let bad = || format!("line {}: bad line \"{}\"", i + 1, line.trim());
+        match words.first() {
+            Some(&"vertex") => {
+                if words.len() != 4 || in_facet == 0 || in_facet > 3 {
+                    return Err(bad());
+                }
+                let mut v = [0.0; 3];
+                for (x, w) in v.iter_mut().zip(words[1..].iter()) {
+                    *x = w.parse().map_err(|_| bad())?;
+                }
+                positions.push(Vec3::new(v[0], v[1], v[2]));
+                in_facet += 1;
+            }
+            Some(&"facet") => {
+                if in_facet != 0 {
+                    return Err(bad());
+                }
+                in_facet = 1;
+            }
+            Some(&"endfacet") => {
+                if in_facet != 4 {
+                    return Err(format!("line {}: facet without 3 vertices", i + 1));
+                }
+                in_facet = 0;
+            }
+            Some(&"solid") | Some(&"endsolid") | Some(&"outer") | Some(&"endloop") | None => {}
+            Some(_) => return Err(bad()),
+        }
+    }
+    if in_facet != 0 {
+        return Err("the last facet is not closed".to_string());
+    }
+    Ok(positions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRIANGLES: [[[f32; 3]; 3]; 2] = [
+        [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+        [[0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+    ];
+
+    fn ascii() -> String {
+        let mut text = "solid test\n".to_string();
+        for t in TRIANGLES.iter() {
+            text += "  facet normal 0 0 0\n    outer loop\n";
+            for v in t.iter() {
+                text += &format!("      vertex {} {} {}\n", v[0], v[1], v[2]);
+            }
+            text += "    endloop\n  endfacet\n";
+        }
+        text + "endsolid test\n"
+    }
+
+    #[test]
+    fn test_ascii_and_binary_agree() {
+        // A header starting with "solid" must not fool the format check.
+        let mut bytes = b"solid but binary".to_vec();
+        bytes.resize(80, 0);
+        bytes.extend_from_slice(&(TRIANGLES.len() as u32).to_le_bytes());
+        for t in TRIANGLES.iter() {
+            bytes.extend_from_slice(&[0; 12]);
+            for x in t.iter().flatten() {
+                bytes.extend_from_slice(&x.to_le_bytes());
+            }
+            bytes.extend_from_slice(&[0; 2]);
+        }
+        let a = parse_stl(ascii().as_bytes(), None).unwrap();
+        let b = parse_stl(&bytes, None).unwrap();
+        assert_eq!(a.positions, b.positions);
+        assert_eq!(a.indices, b.indices);
+        assert_eq!(a.positions[5], Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(a.indices[1], [3, 4, 5]);
+    }
+
+    #[test]
+    fn test_malformed_files() {
+        let err = |text: &str| parse_stl(text.as_bytes(), None).err().unwrap();
+        assert_eq!(err("ply\n"), "neither an ASCII nor a binary STL file");
+        assert_eq!(err("solid empty\nendsolid empty\n"), "no facets");
+        let text = ascii().replacen("vertex 1 0 0", "vertex 1 x 0", 1);
+        assert_eq!(err(&text), "line 5: bad line \"vertex 1 x 0\"");
+        let text = ascii().replacen("      vertex 0 1 0\n", "", 1);
+        assert_eq!(err(&text), "line 7: facet without 3 vertices");
+    }
+}
@@ -1,6 +1,6 @@
 use std::{path::Path, sync::Arc};
 
-use crate::clamp;
+use crate::camera::clamp;
 
 use image::GenericImageView;
 
@@ -113,8 +113,17 @@ pub struct ImageTexture {
 
 impl ImageTexture {
     pub fn new(filename: &str) -> Self {
+        Self::load(filename).unwrap()
+    }
+
+    // Like `new`, but a missing or undecodable file is an error rather than a panic.
+    pub fn load(filename: &str) -> Result<Self, String> {
         let _components_per_pixel = BYTES_PER_PIXEL;
-        let im = image::open(&Path::new(filename)).unwrap();
+        let im = image::open(&Path::new(filename)).map_err(|e| format!("{}: {}", filename, e))?;
+        Ok(Self::from_image(im))
+    }
+
+    pub fn from_image(im: image::DynamicImage) -> Self {
         Self {
             width: im.dimensions().0 as i32,
             height: im.dimensions().1 as i32,
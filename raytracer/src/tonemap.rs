@@ -0,0 +1,99 @@
+use crate::{camera::clamp, Vec3};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operator {
+    // Only clamps, values above 1.0 burn out to white.
+    None,
+    Reinhard,
+    // Reinhard that maps `white` (and anything brighter) to 1.0.
+    ReinhardExtended { white: f64 },
+    // Narkowicz's fit of the ACES filmic curve.
+    Aces,
+    // John Hable's Uncharted 2 filmic curve.
+    Hable,
+}
+
+impl Operator {
+    pub fn from_name(name: &str, white: f64) -> Option<Self> {
+        match name {
+            "none" => Some(Operator::None),
+            "reinhard" => Some(Operator::Reinhard),
+            "reinhard-extended" => Some(Operator::ReinhardExtended { white }),
+            "aces" => Some(Operator::Aces),
+            "hable" => Some(Operator::Hable),
+            _ => None,
+        }
+    }
+
+    pub fn apply(&self, x: f64) -> f64 {
+        match *self {
+            Operator::None => x,
+            Operator::Reinhard => x / (1.0 + x),
+            Operator::ReinhardExtended { white } => x * (1.0 + x / (white * white)) / (1.0 + x),
+            Operator::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+            Operator::Hable => {
+                const EXPOSURE_BIAS: f64 = 2.0;
+                const W: f64 = 11.2;
+                hable_partial(x * EXPOSURE_BIAS) / hable_partial(W)
+            }
+        }
+    }
+}
+
+fn hable_partial(x: f64) -> f64 {
+    let a = 0.15;
+    let b = 0.50;
+    let c = 0.10;
+    let d = 0.20;
+    let e = 0.02;
+    let f = 0.30;
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+pub fn srgb_oetf(x: f64) -> f64 {
+    if x <= 0.003_130_8 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// Linear radiance -> display sRGB: exposure (in stops), tone curve, then the sRGB OETF.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneMapping {
+    pub operator: Operator,
+    pub exposure: f64,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            operator: Operator::None,
+            exposure: 0.0,
+        }
+    }
+}
+
+impl ToneMapping {
+    pub fn new(operator: Operator, exposure: f64) -> Self {
+        Self { operator, exposure }
+    }
+
+    pub fn apply(&self, c: Vec3) -> Vec3 {
+        let scale = 2.0_f64.powf(self.exposure);
+        let map = |x: f64| {
+            let x = if x.is_nan() { 0.0 } else { x.max(0.0) };
+            srgb_oetf(clamp(self.operator.apply(x * scale), 0.0, 1.0))
+        };
+        Vec3::new(map(c.x), map(c.y), map(c.z))
+    }
+
+    pub fn to_rgb8(&self, c: Vec3) -> [u8; 3] {
+        let c = self.apply(c);
+        [
+            (256.0 * clamp(c.x, 0.0, 0.999)) as u8,
+            (256.0 * clamp(c.y, 0.0, 0.999)) as u8,
+            (256.0 * clamp(c.z, 0.0, 0.999)) as u8,
+        ]
+    }
+}
@@ -0,0 +1,182 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::{fmax, fmin, AABB},
+    hittable::{HitRecord, Hittable},
+    matrix::Mat4,
+    Ray, Vec3,
+};
+
+// Places `ptr` in the world with an affine matrix: rays are taken into object space by
+// the inverse, hits are brought back by the matrix, and normals by its inverse transpose.
+pub struct Transform {
+    pub ptr: Arc<dyn Hittable>,
+    pub matrix: Mat4,
+    pub inverse: Mat4,
+}
+
+impl Transform {
+    pub fn new(p: Arc<dyn Hittable>, matrix: Mat4) -> Result<Self, String> {
+        let inverse = matrix
+            .inverse()
+            .ok_or_else(|| "the transform is singular".to_string())?;
+        Ok(Self {
+            ptr: p,
+            matrix,
+            inverse,
+        })
+    }
+
+    fn to_object(&self, r: &Ray) -> Ray {
+        Ray::new(
+            self.inverse.transform_point(r.orig),
+            self.inverse.transform_vector(r.dir),
+            r.time,
+        )
+    }
+}
+
+impl Hittable for Transform {
+    // The object-space direction is not renormalised, so t means the same in both spaces.
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut rec = self.ptr.hit(&self.to_object(r), t_min, t_max)?;
+        rec.p = self.matrix.transform_point(rec.p);
+        // The inverse transpose keeps dot(normal, dir) signs, so front_face still holds.
+        rec.normal = Mat4::transform_normal(&self.inverse, rec.normal).unit();
+        Some(rec)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+        let bbox = self.ptr.bounding_box(time0, time1)?;
+        let mut min = Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = -min;
+        for i in 0..8 {
+            let corner = Vec3::new(
+                if i & 1 == 0 {
+                    bbox.minmum.x
+                } else {
+                    bbox.maxmum.x
+                },
+                if i & 2 == 0 {
+                    bbox.minmum.y
+                } else {
+                    bbox.maxmum.y
+                },
+                if i & 4 == 0 {
+                    bbox.minmum.z
+                } else {
+                    bbox.maxmum.z
+                },
+            );
+            let p = self.matrix.transform_point(corner);
+            min = Vec3::new(fmin(min.x, p.x), fmin(min.y, p.y), fmin(min.z, p.z));
+            max = Vec3::new(fmax(max.x, p.x), fmax(max.y, p.y), fmax(max.z, p.z));
+        }
+        Some(AABB::new(min, max))
+    }
+
+    // The object's pdf is per object-space solid angle. Directions map by w' = A^-1 w,
+    // renormalised, which stretches solid angle by |det A| |A^-1 w|^3 for a unit w.
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
+        let w = self.inverse.transform_vector(v.unit());
+        let len = w.len();
+        let pdf = self
+            .ptr
+            .pdf_value(&self.inverse.transform_point(*o), &(w / len));
+        pdf / (self.matrix.linear_determinant().abs() * len * len * len)
+    }
+
+    fn random(&self, o: Vec3) -> Vec3 {
+        let dir = self.ptr.random(self.inverse.transform_point(o));
+        self.matrix.transform_vector(dir)
+    }
+
+    // Areas scale by |det A|^(2/3) under a uniform scale, which is close enough to weigh
+    // objects and lights against each other.
+    fn area(&self) -> f64 {
+        self.ptr.area() * self.matrix.linear_determinant().abs().powf(2.0 / 3.0)
+    }
+
+    fn power(&self) -> f64 {
+        self.ptr.power() * self.matrix.linear_determinant().abs().powf(2.0 / 3.0)
+    }
+
+    fn emitters(self: Arc<Self>) -> Vec<Arc<dyn Hittable>> {
+        self.ptr
+            .clone()
+            .emitters()
+            .into_iter()
+            .map(|light| {
+                Arc::new(Transform {
+                    ptr: light,
+                    matrix: self.matrix,
+                    inverse: self.inverse,
+                }) as Arc<dyn Hittable>
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{materia::Lambertian, sphere::Sphere};
+
+    fn stretched_sphere() -> Transform {
+        let sphere = Arc::new(Sphere::new(
+            Vec3::zero(),
+            1.0,
+            Arc::new(Lambertian::new(Vec3::ones())),
+        ));
+        let m = Mat4::translate(Vec3::new(0.0, 0.0, -10.0))
+            * Mat4::rotate_x(30.0)
+            * Mat4::scale(Vec3::new(3.0, 1.0, 0.5));
+        Transform::new(sphere, m).unwrap()
+    }
+
+    #[test]
+    fn test_hit_and_bounding_box() {
+        let t = stretched_sphere();
+        let r = Ray::new(Vec3::new(2.9, 0.0, 0.0), Vec3::new(0.0, 0.0, -2.0), 0.0);
+        let rec = t.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((r.at(rec.t) - rec.p).len() < 1e-9);
+        assert!(rec.front_face && Vec3::dot(rec.normal, r.dir) < 0.0);
+        assert!((rec.normal.len() - 1.0).abs() < 1e-12);
+        // The normal stays perpendicular to the surface: nearby hits lie in its plane.
+        let r2 = Ray::new(Vec3::new(2.9, 0.001, 0.0), Vec3::new(0.0, 0.0, -2.0), 0.0);
+        let p2 = t.hit(&r2, 0.001, f64::INFINITY).unwrap().p;
+        assert!(Vec3::dot(p2 - rec.p, rec.normal).abs() < 1e-5);
+
+        let bbox = t.bounding_box(0.0, 1.0).unwrap();
+        assert!((bbox.minmum.x + 3.0).abs() < 1e-12 && (bbox.maxmum.x - 3.0).abs() < 1e-12);
+        let c = 30f64.to_radians().cos();
+        let s = 30f64.to_radians().sin();
+        assert!((bbox.maxmum.y - (c + 0.5 * s)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_pdf_integrates_to_one() {
+        // Sum pdf over a fine grid of directions around the object; every direction
+        // that hits it has to contribute, and the total solid-angle integral must be 1.
+        let t = stretched_sphere();
+        let o = Vec3::zero();
+        let (n_theta, n_phi) = (800, 1600);
+        let mut total = 0.0;
+        for i in 0..n_theta {
+            let theta = std::f64::consts::PI * (i as f64 + 0.5) / n_theta as f64;
+            let d_omega = theta.sin()
+                * (std::f64::consts::PI / n_theta as f64)
+                * (2.0 * std::f64::consts::PI / n_phi as f64);
+            for j in 0..n_phi {
+                let phi = 2.0 * std::f64::consts::PI * (j as f64 + 0.5) / n_phi as f64;
+                let v = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                );
+                total += t.pdf_value(&o, &v) * d_omega;
+            }
+        }
+        assert!((total - 1.0).abs() < 0.02, "{}", total);
+    }
+}
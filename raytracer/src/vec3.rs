@@ -54,6 +54,11 @@ impl Vec3 {
         Self::new(a.x * b.x, a.y * b.y, a.z * b.z)
     }
 
+    // Of a linear sRGB colour, with the Rec. 709 weights.
+    pub fn luminance(self) -> f64 {
+        0.2126 * self.x + 0.7152 * self.y + 0.0722 * self.z
+    }
+
     pub fn cross(a: Self, b: Self) -> Self {
         Self::new(
             a.y * b.z - b.y * a.z,
@@ -0,0 +1,255 @@
+use std::{f64::consts::PI, sync::Arc};
+
+use raytracer::{
+    aarec::XyRect,
+    aov::Aov,
+    camera::Camera,
+    environment::EnvironmentMap,
+    hittable::ObjectId,
+    hittable_list::HittableList,
+    light::{Light, PointLight},
+    materia::{DiffuseLight, Lambertian},
+    sphere::Sphere,
+    RenderSettings, Renderer, Vec3,
+};
+
+fn settings() -> RenderSettings {
+    RenderSettings {
+        width: 8,
+        height: 6,
+        samples_per_pixel: 4,
+        max_depth: 5,
+        threads: 2,
+        jobs: 3,
+        ..RenderSettings::default()
+    }
+}
+
+fn camera() -> Camera {
+    Camera::new(
+        Vec3::new(0.0, 0.0, 5.0),
+        Vec3::zero(),
+        Vec3::new(0.0, 1.0, 0.0),
+        40.0,
+        8.0 / 6.0,
+        0.0,
+        5.0,
+        0.0,
+        1.0,
+    )
+}
+
+#[test]
+fn test_empty_world_shows_background() {
+    let renderer = Renderer::new(
+        settings(),
+        Arc::new(HittableList::new()),
+        Arc::new(HittableList::new()),
+        camera(),
+        Vec3::new(0.25, 0.25, 0.25),
+    );
+    let img = renderer.render();
+    assert_eq!(img.dimensions(), (8, 6));
+    for pixel in img.pixels() {
+        assert_eq!(pixel.0, [137, 137, 137]);
+    }
+}
+
+#[test]
+fn test_light_fills_view() {
+    let mut world = HittableList::new();
+    world.add(Arc::new(Sphere::new(
+        Vec3::zero(),
+        100.0,
+        Arc::new(DiffuseLight::new1(Vec3::new(4.0, 4.0, 4.0))),
+    )));
+    let renderer = Renderer::new(
+        settings(),
+        Arc::new(world),
+        Arc::new(HittableList::new()),
+        camera(),
+        Vec3::zero(),
+    );
+    for pixel in renderer.render().pixels() {
+        assert_eq!(pixel.0, [255, 255, 255]);
+    }
+}
+
+#[test]
+fn test_aovs_from_first_hit() {
+    let mut world = HittableList::new();
+    let sphere = Sphere::new(
+        Vec3::zero(),
+        100.0,
+        Arc::new(DiffuseLight::new1(Vec3::new(4.0, 4.0, 4.0))),
+    );
+    world.add(Arc::new(ObjectId::new(Arc::new(sphere), 7)));
+    let mut settings = settings();
+    settings.aovs = vec![Aov::ObjectId, Aov::Depth, Aov::Albedo];
+    let renderer = Renderer::new(
+        settings,
+        Arc::new(world),
+        Arc::new(HittableList::new()),
+        camera(),
+        Vec3::zero(),
+    );
+    let output = renderer.render_passes_with_progress(|_, _| {});
+    assert_eq!(output.aovs.len(), 3);
+    for c in output.aovs[0].1.pixels.iter() {
+        assert_eq!(c.x, 7.0);
+    }
+    // The camera sits 5 units from the centre of the sphere.
+    for c in output.aovs[1].1.pixels.iter() {
+        assert!(c.x > 95.0 && c.x < 105.0);
+    }
+    for c in output.aovs[2].1.pixels.iter() {
+        assert_eq!(*c, Vec3::ones());
+    }
+}
+
+fn lit_sphere(settings: RenderSettings) -> Renderer {
+    let mut world = HittableList::new();
+    world.add(Arc::new(Sphere::new(
+        Vec3::zero(),
+        1.0,
+        Arc::new(Lambertian::new(Vec3::new(0.5, 0.6, 0.7))),
+    )));
+    let light = Arc::new(Sphere::new(
+        Vec3::new(2.0, 3.0, 2.0),
+        0.5,
+        Arc::new(DiffuseLight::new1(Vec3::new(8.0, 8.0, 8.0))),
+    ));
+    world.add(light.clone());
+    let mut lights = HittableList::new();
+    lights.add(light);
+    Renderer::new(
+        settings,
+        Arc::new(world),
+        Arc::new(lights),
+        camera(),
+        Vec3::new(0.1, 0.1, 0.1),
+    )
+}
+
+#[test]
+fn test_same_seed_same_image() {
+    let mut one_thread = settings();
+    one_thread.threads = 1;
+    one_thread.jobs = 1;
+    let mut many_threads = settings();
+    many_threads.threads = 4;
+    many_threads.jobs = 6;
+    let a = lit_sphere(one_thread).render_linear();
+    let b = lit_sphere(many_threads.clone()).render_linear();
+    assert_eq!(a, b);
+
+    many_threads.seed = 1;
+    assert_ne!(a, lit_sphere(many_threads).render_linear());
+}
+
+#[test]
+fn test_light_sampling_converges_to_the_same_image() {
+    // A sphere lit from the front by a small bright one, in the dark.
+    let render = |sample_lights: bool, seed: u64| {
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere::new(
+            Vec3::zero(),
+            1.5,
+            Arc::new(Lambertian::new(Vec3::new(0.5, 0.6, 0.7))),
+        )));
+        let light = Arc::new(Sphere::new(
+            Vec3::new(1.0, 1.5, 2.5),
+            0.25,
+            Arc::new(DiffuseLight::new1(Vec3::new(40.0, 40.0, 40.0))),
+        ));
+        world.add(light.clone());
+        let mut lights = HittableList::new();
+        if sample_lights {
+            lights.add(light);
+        }
+        let mut settings = settings();
+        settings.samples_per_pixel = 256;
+        settings.seed = seed;
+        let renderer = Renderer::new(
+            settings,
+            Arc::new(world),
+            Arc::new(lights),
+            camera(),
+            Vec3::zero(),
+        );
+        renderer.render_linear().pixels
+    };
+    // How far two renders with different seeds are apart, a measure of the noise.
+    let spread = |a: &[Vec3], b: &[Vec3]| -> f64 {
+        a.iter()
+            .zip(b.iter())
+            .map(|(x, y)| (*x - *y).len_squared())
+            .sum()
+    };
+    let (mis, mis2) = (render(true, 0), render(true, 1));
+    let (bsdf, bsdf2) = (render(false, 0), render(false, 1));
+    let total = |pixels: &[Vec3]| pixels.iter().fold(Vec3::zero(), |a, b| a + *b);
+    let (a, b) = (total(&mis) + total(&mis2), total(&bsdf) + total(&bsdf2));
+    assert!((a - b).len() < 0.05 * b.len(), "{:?} vs {:?}", a, b);
+    assert!(spread(&mis, &mis2) * 4.0 < spread(&bsdf, &bsdf2));
+}
+
+#[test]
+fn test_point_light_follows_inverse_square() {
+    let mut world = HittableList::new();
+    world.add(Arc::new(XyRect::new(
+        -10.0,
+        10.0,
+        -10.0,
+        10.0,
+        0.0,
+        Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+    )));
+    let mut renderer = Renderer::new(
+        settings(),
+        Arc::new(world),
+        Arc::new(HittableList::new()),
+        camera(),
+        Vec3::zero(),
+    );
+    // 20 units above the plane, E = I / 400 = pi, so the radiance is 0.5 / pi * pi (less
+    // the cos^3 falloff, about 6% in the corners).
+    let light = PointLight::new(Vec3::new(0.0, 0.0, 20.0), Vec3::ones() * (400.0 * PI));
+    renderer.punctual_lights = Arc::new(vec![Arc::new(light) as Arc<dyn Light>]);
+    for c in renderer.render_linear().pixels.iter() {
+        assert!(c.x > 0.46 && c.x < 0.5 + 1e-9, "{:?}", c);
+    }
+}
+
+#[test]
+fn test_environment_lights_a_convex_object_once() {
+    let mut world = HittableList::new();
+    world.add(Arc::new(Sphere::new(
+        Vec3::zero(),
+        1.0,
+        Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+    )));
+    let mut renderer = Renderer::new(
+        RenderSettings {
+            samples_per_pixel: 64,
+            ..settings()
+        },
+        Arc::new(world),
+        Arc::new(HittableList::new()),
+        camera(),
+        Vec3::zero(),
+    );
+    renderer.environment = Some(Arc::new(EnvironmentMap::new(
+        4,
+        2,
+        vec![Vec3::ones(); 8],
+        0.0,
+        1.0,
+    )));
+    let image = renderer.render_linear();
+    // Light reflected off a convex object leaves the scene, so its radiance is the albedo
+    // times the uniform surroundings. Pixel (3, 3) lies wholly on the sphere.
+    let c = image.get(3, 3);
+    assert!((c.x - 0.5).abs() < 0.02, "{:?}", c);
+    assert!((image.get(0, 0).x - 1.0).abs() < 1e-9);
+}